@@ -0,0 +1,78 @@
+//! Compares the throughput of the block cipher backends.
+//!
+//! Run with `cargo bench --bench backends --features bench`. Each backend encrypts the same
+//! buffer repeatedly and the best of several runs is reported, which keeps the numbers stable
+//! without pulling in a benchmarking framework. The detected ciphers use AES-NI where the CPU supports it, while the
+//! reference ciphers always run the software rounds, so the two can be compared on any machine.
+//! Running again with `--features bench,table-sbox` shows what the constant-time S-box costs
+//! the reference rows.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use raes::{ttable, Aes128, Aes256, BlockCipher, Key};
+
+const BUFFER_LEN: usize = 64 * 1024;
+const RUNS: usize = 5;
+
+/// Times `encrypt` over a fresh buffer and prints the best throughput in MiB/s.
+fn bench(name: &str, mut encrypt: impl FnMut(&mut [[u8; 16]])) {
+    let mut blocks = vec![[0x5a; 16]; BUFFER_LEN / 16];
+    let mut best = Duration::MAX;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        encrypt(black_box(&mut blocks));
+        best = best.min(start.elapsed());
+    }
+
+    let mib_per_sec = BUFFER_LEN as f64 / (1024.0 * 1024.0) / best.as_secs_f64();
+    println!("{:<24} {:>10.2} MiB/s", name, mib_per_sec);
+}
+
+fn bench_blocks<C: BlockCipher>(name: &str, cipher: &C) {
+    bench(name, |blocks| {
+        for block in blocks.iter_mut() {
+            *block = cipher.encrypt_block(block);
+        }
+    });
+}
+
+fn main() {
+    let aes128 = Aes128::new(&Key::new([0x24; 16]));
+    let aes256 = Aes256::new(&Key::new([0x24; 32]));
+
+    bench_blocks(
+        "reference AES-128",
+        &Aes128::software_only(&Key::new([0x24; 16])),
+    );
+    bench_blocks(
+        "reference AES-256",
+        &Aes256::software_only(&Key::new([0x24; 32])),
+    );
+
+    bench_blocks("detected AES-128", &aes128);
+    bench_blocks("detected AES-256", &aes256);
+
+    bench("batched AES-128", |blocks| aes128.encrypt_blocks(blocks));
+    bench("batched AES-256", |blocks| aes256.encrypt_blocks(blocks));
+
+    bench("bitsliced AES-128", |blocks| {
+        for chunk in blocks.chunks_exact_mut(8) {
+            aes128.encrypt_8_blocks(chunk.try_into().unwrap());
+        }
+    });
+    bench("bitsliced AES-256", |blocks| {
+        for chunk in blocks.chunks_exact_mut(8) {
+            aes256.encrypt_8_blocks(chunk.try_into().unwrap());
+        }
+    });
+
+    bench_blocks(
+        "T-table AES-128",
+        &ttable::Aes128::new(&Key::new([0x24; 16])),
+    );
+    bench_blocks(
+        "T-table AES-256",
+        &ttable::Aes256::new(&Key::new([0x24; 32])),
+    );
+}
@@ -1,52 +1,58 @@
+use raes::parallel::{par_decrypt_file, par_encrypt_file, Mode};
 use raes::shared::{decrypt_stream, encrypt_stream};
-use rand::{distributions::Alphanumeric, Rng};
-use std::convert::TryInto;
+use raes::{Key256, Result};
 use std::fs::{self, File};
-use std::io::{self, BufReader, BufWriter};
+use std::io::{BufReader, BufWriter};
 use std::path::Path;
 use structopt::StructOpt;
-
-/// Generate a random key for encryption.
-fn generate_key() -> Vec<u8> {
-    rand::thread_rng()
-        .sample_iter(&Alphanumeric)
-        .take(32) // Adjust the size as needed for the encryption algorithm
-        .collect()
-}
+use zeroize::Zeroizing;
 
 /// Encrypts the input file and writes the encrypted data and key to separate files.
-fn encrypt(input_path: &Path, output_path: &Path, key_path: &Path) -> io::Result<()> {
-    let key = generate_key();
-    let input_file = File::open(input_path)?;
-    let output_file = File::create(output_path)?;
-    let mut reader = BufReader::new(input_file);
-    let mut writer = BufWriter::new(output_file);
+///
+/// With `threads`, the file is split across that many worker threads instead of being streamed.
+fn encrypt(
+    input_path: &Path,
+    output_path: &Path,
+    key_path: &Path,
+    threads: Option<usize>,
+) -> Result<()> {
+    let key = Key256::generate();
+    if let Some(threads) = threads {
+        par_encrypt_file(input_path, output_path, &key, &Mode::Ecb, threads)?;
+    } else {
+        let input_file = File::open(input_path)?;
+        let output_file = File::create(output_path)?;
+        let mut reader = BufReader::new(input_file);
+        let mut writer = BufWriter::new(output_file);
 
-    encrypt_stream(
-        &mut reader,
-        &mut writer,
-        &key.as_slice().try_into().unwrap(),
-    )?;
+        encrypt_stream(&mut reader, &mut writer, &key)?;
+    }
 
     // Write the key to a file
-    fs::write(key_path, &key.as_slice())?;
+    fs::write(key_path, key.as_bytes())?;
 
     Ok(())
 }
 
 /// Decrypts the input file using the provided key file and writes the output to the specified file.
-fn decrypt(input_path: &Path, output_path: &Path, key_path: &Path) -> io::Result<()> {
-    let key = fs::read(key_path)?;
-    let input_file = File::open(input_path)?;
-    let output_file = File::create(output_path)?;
-    let mut reader = BufReader::new(input_file);
-    let mut writer = BufWriter::new(output_file);
+fn decrypt(
+    input_path: &Path,
+    output_path: &Path,
+    key_path: &Path,
+    threads: Option<usize>,
+) -> Result<()> {
+    // The file contents are wiped as soon as they have been copied into the key.
+    let key = Key256::from_slice(&Zeroizing::new(fs::read(key_path)?))?;
+    if let Some(threads) = threads {
+        par_decrypt_file(input_path, output_path, &key, &Mode::Ecb, threads)?;
+    } else {
+        let input_file = File::open(input_path)?;
+        let output_file = File::create(output_path)?;
+        let mut reader = BufReader::new(input_file);
+        let mut writer = BufWriter::new(output_file);
 
-    decrypt_stream(
-        &mut reader,
-        &mut writer,
-        &key.as_slice().try_into().unwrap(),
-    )?;
+        decrypt_stream(&mut reader, &mut writer, &key)?;
+    }
 
     Ok(())
 }
@@ -69,6 +75,10 @@ struct Opt {
     /// Key file path for encryption/decryption
     #[structopt(long, value_name = "KEY_FILE")]
     key_file: Option<String>,
+
+    /// Splits the work across this many threads; the output is the same as without it
+    #[structopt(long, value_name = "N")]
+    threads: Option<usize>,
 }
 
 fn main() {
@@ -79,7 +89,7 @@ fn main() {
             let input_path = Path::new(input);
             let output_path = Path::new(opt.output.as_ref().unwrap());
             let key_path = Path::new(opt.key_file.as_ref().unwrap());
-            if let Err(e) = encrypt(input_path, output_path, key_path) {
+            if let Err(e) = encrypt(input_path, output_path, key_path, opt.threads) {
                 eprintln!("Error during encryption: {}", e);
             }
         }
@@ -87,7 +97,7 @@ fn main() {
             let input_path = Path::new(input);
             let output_path = Path::new(opt.output.as_ref().unwrap());
             let key_path = Path::new(opt.key_file.as_ref().unwrap());
-            if let Err(e) = decrypt(input_path, output_path, key_path) {
+            if let Err(e) = decrypt(input_path, output_path, key_path, opt.threads) {
                 eprintln!("Error during decryption: {}", e);
             }
         }
@@ -0,0 +1,301 @@
+// This file holds the hardware backend built on the x86_64 AES-NI instructions.
+//
+// AESENC and AESDEC each perform a whole round in constant time, and AESKEYGENASSIST supplies
+// the SubWord and RotWord steps of the key expansion. The instructions are optional, so the
+// backend is only built after `is_x86_feature_detected!` has confirmed them; the ciphers fall
+// back to the software rounds otherwise.
+
+use std::arch::x86_64::{
+    __m128i, _mm_aesdec_si128, _mm_aesdeclast_si128, _mm_aesenc_si128, _mm_aesenclast_si128,
+    _mm_aesimc_si128, _mm_aeskeygenassist_si128, _mm_cvtsi128_si32, _mm_loadu_si128,
+    _mm_set1_epi32, _mm_setzero_si128, _mm_srli_si128, _mm_storeu_si128, _mm_xor_si128,
+};
+use std::{array, fmt};
+
+use zeroize::Zeroize;
+
+use crate::constant::ROUND_CONSTANTS;
+use crate::shared::rounds_for_key_len;
+
+/// The number of blocks kept in flight by the batch functions.
+const PIPELINE: usize = 8;
+
+/// Both key schedules laid out as the 16-byte round keys the instructions consume, wiped when
+/// dropped.
+#[derive(Clone)]
+pub(crate) struct AesNi {
+    round_keys: [[u8; 16]; 15],
+    inverse_round_keys: [[u8; 16]; 15],
+    rounds: usize,
+}
+
+impl AesNi {
+    /// Expands the key with AESKEYGENASSIST, or returns `None` if the CPU lacks AES-NI.
+    pub(crate) fn new(key: &[u8]) -> Option<Self> {
+        if !is_x86_feature_detected!("aes") {
+            return None;
+        }
+
+        // SAFETY: the CPU supports the AES instructions.
+        Some(unsafe { Self::expand(key) })
+    }
+
+    pub(crate) fn encrypt_block(&self, block: &[u8; 16]) -> [u8; 16] {
+        let mut block = *block;
+        // SAFETY: `new` only builds the backend when the CPU supports the AES instructions.
+        unsafe {
+            encrypt(
+                &self.round_keys[..=self.rounds],
+                array::from_mut(&mut block),
+            )
+        };
+        block
+    }
+
+    pub(crate) fn decrypt_block(&self, block: &[u8; 16]) -> [u8; 16] {
+        let mut block = *block;
+        // SAFETY: as for `encrypt_block`.
+        unsafe {
+            decrypt(
+                &self.inverse_round_keys[..=self.rounds],
+                array::from_mut(&mut block),
+            )
+        };
+        block
+    }
+
+    /// Encrypts the blocks [`PIPELINE`] at a time, so the rounds of independent blocks overlap.
+    pub(crate) fn encrypt_blocks(&self, blocks: &mut [[u8; 16]]) {
+        let round_keys = &self.round_keys[..=self.rounds];
+        let (groups, rest) = blocks.as_chunks_mut::<PIPELINE>();
+        // SAFETY: as for `encrypt_block`.
+        unsafe {
+            for group in groups {
+                encrypt(round_keys, group);
+            }
+            for block in rest {
+                encrypt(round_keys, array::from_mut(block));
+            }
+        }
+    }
+
+    /// Decrypts the blocks [`PIPELINE`] at a time.
+    pub(crate) fn decrypt_blocks(&self, blocks: &mut [[u8; 16]]) {
+        let round_keys = &self.inverse_round_keys[..=self.rounds];
+        let (groups, rest) = blocks.as_chunks_mut::<PIPELINE>();
+        // SAFETY: as for `encrypt_block`.
+        unsafe {
+            for group in groups {
+                decrypt(round_keys, group);
+            }
+            for block in rest {
+                decrypt(round_keys, array::from_mut(block));
+            }
+        }
+    }
+
+    /// Runs the FIPS-197 key expansion on words held in memory byte order, then derives the
+    /// inverse schedule for AESDEC by applying InvMixColumns (AESIMC) to the inner round keys.
+    #[target_feature(enable = "aes")]
+    unsafe fn expand(key: &[u8]) -> Self {
+        let nr = rounds_for_key_len(key.len());
+        let nk = key.len() / 4;
+
+        let mut words = [0u32; 60];
+        for (word, bytes) in words.iter_mut().zip(key.chunks_exact(4)) {
+            *word = u32::from_le_bytes(bytes.try_into().unwrap());
+        }
+        for i in nk..4 * (nr + 1) {
+            let mut temp = words[i - 1];
+            if i % nk == 0 {
+                temp = rot_sub_word(temp) ^ ROUND_CONSTANTS[i / nk - 1].swap_bytes();
+            } else if nk > 6 && i % nk == 4 {
+                temp = sub_word(temp);
+            }
+            words[i] = words[i - nk] ^ temp;
+        }
+
+        let mut schedule = AesNi {
+            round_keys: [[0; 16]; 15],
+            inverse_round_keys: [[0; 16]; 15],
+            rounds: nr,
+        };
+        for (round_key, round_words) in schedule.round_keys.iter_mut().zip(words.chunks_exact(4)) {
+            for (bytes, word) in round_key.chunks_exact_mut(4).zip(round_words) {
+                bytes.copy_from_slice(&word.to_le_bytes());
+            }
+        }
+        words.zeroize();
+
+        schedule.inverse_round_keys[0] = schedule.round_keys[0];
+        schedule.inverse_round_keys[nr] = schedule.round_keys[nr];
+        for round in 1..nr {
+            let mixed = _mm_aesimc_si128(load(&schedule.round_keys[round]));
+            _mm_storeu_si128(
+                schedule.inverse_round_keys[round].as_mut_ptr().cast(),
+                mixed,
+            );
+        }
+
+        schedule
+    }
+}
+
+impl Drop for AesNi {
+    fn drop(&mut self) {
+        self.round_keys.zeroize();
+        self.inverse_round_keys.zeroize();
+    }
+}
+
+impl fmt::Debug for AesNi {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "AesNi {{ rounds: {}, round_keys: <redacted> }}",
+            self.rounds
+        )
+    }
+}
+
+/// AESKEYGENASSIST returns SubWord of dword 1 in dword 0 and RotWord(SubWord) of it in dword 1.
+/// With the same word in every lane that is exactly the two steps the key expansion needs; the
+/// round constant is left at zero and added by the caller.
+#[target_feature(enable = "aes")]
+unsafe fn assist(word: u32) -> __m128i {
+    _mm_aeskeygenassist_si128::<0>(_mm_set1_epi32(word as i32))
+}
+
+#[target_feature(enable = "aes")]
+unsafe fn sub_word(word: u32) -> u32 {
+    _mm_cvtsi128_si32(assist(word)) as u32
+}
+
+#[target_feature(enable = "aes")]
+unsafe fn rot_sub_word(word: u32) -> u32 {
+    _mm_cvtsi128_si32(_mm_srli_si128::<4>(assist(word))) as u32
+}
+
+#[target_feature(enable = "aes")]
+unsafe fn load(block: &[u8; 16]) -> __m128i {
+    _mm_loadu_si128(block.as_ptr().cast())
+}
+
+#[target_feature(enable = "aes")]
+unsafe fn store(state: __m128i) -> [u8; 16] {
+    let mut block = [0; 16];
+    _mm_storeu_si128(block.as_mut_ptr().cast(), state);
+    block
+}
+
+/// Encrypts `N` independent blocks, issuing each round for all of them before the next so the
+/// instruction latencies overlap.
+#[target_feature(enable = "aes")]
+unsafe fn encrypt<const N: usize>(round_keys: &[[u8; 16]], blocks: &mut [[u8; 16]; N]) {
+    let nr = round_keys.len() - 1;
+    let mut states = [_mm_setzero_si128(); N];
+
+    let round_key = load(&round_keys[0]);
+    for (state, block) in states.iter_mut().zip(blocks.iter()) {
+        *state = _mm_xor_si128(load(block), round_key);
+    }
+    for round_key in &round_keys[1..nr] {
+        let round_key = load(round_key);
+        for state in states.iter_mut() {
+            *state = _mm_aesenc_si128(*state, round_key);
+        }
+    }
+    let round_key = load(&round_keys[nr]);
+    for (state, block) in states.iter().zip(blocks.iter_mut()) {
+        *block = store(_mm_aesenclast_si128(*state, round_key));
+    }
+}
+
+/// Decrypts `N` independent blocks with the equivalent inverse cipher.
+#[target_feature(enable = "aes")]
+unsafe fn decrypt<const N: usize>(round_keys: &[[u8; 16]], blocks: &mut [[u8; 16]; N]) {
+    let nr = round_keys.len() - 1;
+    let mut states = [_mm_setzero_si128(); N];
+
+    let round_key = load(&round_keys[nr]);
+    for (state, block) in states.iter_mut().zip(blocks.iter()) {
+        *state = _mm_xor_si128(load(block), round_key);
+    }
+    for round_key in round_keys[1..nr].iter().rev() {
+        let round_key = load(round_key);
+        for state in states.iter_mut() {
+            *state = _mm_aesdec_si128(*state, round_key);
+        }
+    }
+    let round_key = load(&round_keys[0]);
+    for (state, block) in states.iter().zip(blocks.iter_mut()) {
+        *block = store(_mm_aesdeclast_si128(*state, round_key));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::{hex, hex_array};
+
+    #[test]
+    fn test_fips_197_vectors() {
+        if !is_x86_feature_detected!("aes") {
+            eprintln!("skipping: this CPU does not support AES-NI");
+            return;
+        }
+
+        let plaintext = hex_array("00112233445566778899aabbccddeeff");
+        let vectors: [(&str, [u8; 16]); 3] = [
+            (
+                "000102030405060708090a0b0c0d0e0f",
+                hex_array("69c4e0d86a7b0430d8cdb78070b4c55a"),
+            ),
+            (
+                "000102030405060708090a0b0c0d0e0f1011121314151617",
+                hex_array("dda97ca4864cdfe06eaf70a0ec0d7191"),
+            ),
+            (
+                "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f",
+                hex_array("8ea2b7ca516745bfeafc49904b496089"),
+            ),
+        ];
+
+        for (key, ciphertext) in vectors {
+            let backend = AesNi::new(&hex(key)).unwrap();
+            assert_eq!(backend.encrypt_block(&plaintext), ciphertext);
+            assert_eq!(backend.decrypt_block(&ciphertext), plaintext);
+        }
+    }
+
+    #[test]
+    fn test_batches_match_single_blocks() {
+        let Some(backend) = AesNi::new(&[0x24; 32]) else {
+            return;
+        };
+
+        // Cover whole pipelines, a partial one and both together.
+        for len in [0, 1, 7, PIPELINE, 2 * PIPELINE + 3] {
+            let plaintext: Vec<[u8; 16]> = (0..len).map(|i| [i as u8; 16]).collect();
+            let mut blocks = plaintext.clone();
+
+            backend.encrypt_blocks(&mut blocks);
+            for (block, original) in blocks.iter().zip(&plaintext) {
+                assert_eq!(*block, backend.encrypt_block(original));
+            }
+
+            backend.decrypt_blocks(&mut blocks);
+            assert_eq!(blocks, plaintext);
+        }
+    }
+
+    #[test]
+    fn test_debug_is_redacted() {
+        if let Some(backend) = AesNi::new(&[0xab; 16]) {
+            assert_eq!(
+                format!("{:?}", backend),
+                "AesNi { rounds: 10, round_keys: <redacted> }"
+            );
+        }
+    }
+}
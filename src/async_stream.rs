@@ -0,0 +1,316 @@
+// This file holds the tokio `AsyncWrite` and `AsyncRead` counterparts of the adapters in
+// `stream`. They produce and consume the same format and share the CBC push cores with it.
+
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::cbc::{
+    generate_iv, parse_stream_header, stream_header, PaddedDecryptor, PaddedEncryptor, HEADER_SIZE,
+    STREAM_CHUNK_SIZE,
+};
+use crate::cipher::BlockCipher;
+use crate::error::{Error, Result};
+
+/// Encrypts everything written to it into the inner writer.
+///
+/// Shutting the writer down writes the padded final block and then shuts down the inner writer;
+/// until then the output is not a complete stream.
+pub struct AsyncEncryptingWriter<W, C> {
+    inner: W,
+    encryptor: Option<PaddedEncryptor<C>>,
+    output: Vec<u8>,
+    written: usize,
+}
+
+impl<W: AsyncWrite + Unpin, C: BlockCipher + Unpin> AsyncEncryptingWriter<W, C> {
+    /// Starts a new stream under a random IV. Nothing is written to `inner` until the first
+    /// write, flush or shutdown.
+    pub fn new(inner: W, cipher: C) -> Self {
+        let iv = generate_iv();
+        AsyncEncryptingWriter {
+            inner,
+            encryptor: Some(PaddedEncryptor::new(cipher, &iv)),
+            output: stream_header(&iv).to_vec(),
+            written: 0,
+        }
+    }
+
+    /// Returns the inner writer.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    /// Writes out any ciphertext that has been produced but not yet accepted by the inner writer.
+    fn poll_write_output(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.written < self.output.len() {
+            let n = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.output[self.written..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.written += n;
+        }
+
+        self.output.clear();
+        self.written = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W: AsyncWrite + Unpin, C: BlockCipher + Unpin> AsyncWrite for AsyncEncryptingWriter<W, C> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_output(cx))?;
+
+        let encryptor = match this.encryptor.as_mut() {
+            Some(encryptor) => encryptor,
+            None => return Poll::Ready(Err(io::Error::other("stream has been shut down"))),
+        };
+
+        // Accept at most one chunk at a time so the pending ciphertext stays bounded.
+        let accepted = buf.len().min(STREAM_CHUNK_SIZE);
+        encryptor.update(&buf[..accepted], &mut this.output);
+        Poll::Ready(Ok(accepted))
+    }
+
+    /// Flushes the inner writer. Up to 15 bytes of plaintext stay buffered until a full block
+    /// is available or the stream is shut down.
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_output(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_output(cx))?;
+
+        if let Some(encryptor) = this.encryptor.take() {
+            encryptor.finish(&mut this.output);
+            ready!(this.poll_write_output(cx))?;
+        }
+
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+/// Decrypts a stream produced by [`AsyncEncryptingWriter`], `stream::EncryptingWriter` or
+/// `cbc::encrypt_stream`, yielding plaintext as it is read.
+///
+/// As with `stream::DecryptingReader`, the last block is held back until the end of the
+/// ciphertext, and a truncated stream or bad padding fails the read that reaches the end and
+/// every read after it. `Error::from` recovers the original [`Error`] from the `io::Error`.
+pub struct AsyncDecryptingReader<R, C> {
+    inner: R,
+    state: State<C>,
+    output: Vec<u8>,
+    position: usize,
+}
+
+enum State<C> {
+    Header {
+        cipher: C,
+        header: [u8; HEADER_SIZE],
+        header_len: usize,
+    },
+    Body(PaddedDecryptor<C>),
+    Finished,
+    Failed,
+}
+
+impl<R: AsyncRead + Unpin, C: BlockCipher + Unpin> AsyncDecryptingReader<R, C> {
+    /// Wraps `inner`. Nothing is read from it until the first read.
+    pub fn new(inner: R, cipher: C) -> Self {
+        AsyncDecryptingReader {
+            inner,
+            state: State::Header {
+                cipher,
+                header: [0; HEADER_SIZE],
+                header_len: 0,
+            },
+            output: Vec::with_capacity(STREAM_CHUNK_SIZE),
+            position: 0,
+        }
+    }
+
+    /// Returns the inner reader, discarding any plaintext that has not been read yet.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Reads and decrypts more of the stream into `output`, which must be empty.
+    fn poll_fill_output(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        match &mut self.state {
+            State::Header {
+                header, header_len, ..
+            } => {
+                let mut buf = ReadBuf::new(&mut header[*header_len..]);
+                ready!(Pin::new(&mut self.inner).poll_read(cx, &mut buf))?;
+                let read_size = buf.filled().len();
+                *header_len += read_size;
+
+                if read_size == 0 {
+                    self.state = State::Failed;
+                    return Poll::Ready(Err(Error::TruncatedInput));
+                }
+
+                if *header_len == HEADER_SIZE {
+                    if let State::Header { cipher, header, .. } =
+                        std::mem::replace(&mut self.state, State::Failed)
+                    {
+                        let iv = parse_stream_header(&header)?;
+                        self.state = State::Body(PaddedDecryptor::new(cipher, &iv));
+                    }
+                }
+            }
+            State::Body(decryptor) => {
+                let mut chunk = [0u8; STREAM_CHUNK_SIZE];
+                let mut buf = ReadBuf::new(&mut chunk);
+                ready!(Pin::new(&mut self.inner).poll_read(cx, &mut buf))?;
+                if !buf.filled().is_empty() {
+                    decryptor.update(buf.filled(), &mut self.output);
+                    return Poll::Ready(Ok(()));
+                }
+
+                if let State::Body(decryptor) = std::mem::replace(&mut self.state, State::Failed) {
+                    decryptor.finish(&mut self.output)?;
+                    self.state = State::Finished;
+                }
+            }
+            State::Finished => {}
+            State::Failed => {
+                return Poll::Ready(Err(Error::Io(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "stream has already failed to decrypt",
+                ))));
+            }
+        }
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<R: AsyncRead + Unpin, C: BlockCipher + Unpin> AsyncRead for AsyncDecryptingReader<R, C> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if buf.remaining() == 0 {
+            return Poll::Ready(Ok(()));
+        }
+
+        while this.position == this.output.len() {
+            if let State::Finished = this.state {
+                return Poll::Ready(Ok(()));
+            }
+            this.output.clear();
+            this.position = 0;
+            ready!(this.poll_fill_output(cx))?;
+        }
+
+        let len = buf.remaining().min(this.output.len() - this.position);
+        buf.put_slice(&this.output[this.position..this.position + len]);
+        this.position += len;
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+    use crate::cbc;
+    use crate::cipher::Aes256;
+    use crate::key::Key;
+    use crate::stream::{DecryptingReader, EncryptingWriter};
+
+    #[tokio::test]
+    async fn test_round_trip_over_duplex() {
+        for len in [0, 1, 15, 16, 17, 1000, 20000] {
+            let plaintext: Vec<u8> = (0..len).map(|i| (i * 11) as u8).collect();
+
+            // A small duplex buffer forces both adapters through their pending paths.
+            let (client, server) = duplex(37);
+
+            let sent = plaintext.clone();
+            let writer = tokio::spawn(async move {
+                let cipher = Aes256::new(&Key::new([2; 32]));
+                let mut writer = AsyncEncryptingWriter::new(client, cipher);
+                for piece in sent.chunks(100) {
+                    writer.write_all(piece).await.unwrap();
+                }
+                writer.shutdown().await.unwrap();
+            });
+
+            let cipher = Aes256::new(&Key::new([2; 32]));
+            let mut reader = AsyncDecryptingReader::new(server, &cipher);
+            let mut decrypted = Vec::new();
+            reader.read_to_end(&mut decrypted).await.unwrap();
+            writer.await.unwrap();
+
+            assert_eq!(decrypted, plaintext);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_compatible_with_blocking_adapters() {
+        let cipher = Aes256::new(&Key::new([6; 32]));
+        let plaintext = b"the same format on both sides of the runtime".repeat(10);
+
+        let mut writer = EncryptingWriter::new(Vec::new(), &cipher);
+        std::io::Write::write_all(&mut writer, &plaintext).unwrap();
+        let encrypted = writer.finish().unwrap();
+
+        let mut decrypted = Vec::new();
+        AsyncDecryptingReader::new(&encrypted[..], &cipher)
+            .read_to_end(&mut decrypted)
+            .await
+            .unwrap();
+        assert_eq!(decrypted, plaintext);
+
+        let mut writer = AsyncEncryptingWriter::new(Vec::new(), &cipher);
+        writer.write_all(&plaintext).await.unwrap();
+        writer.shutdown().await.unwrap();
+        let encrypted = writer.into_inner();
+
+        let mut decrypted = Vec::new();
+        std::io::Read::read_to_end(
+            &mut DecryptingReader::new(Cursor::new(&encrypted), &cipher),
+            &mut decrypted,
+        )
+        .unwrap();
+        assert_eq!(decrypted, plaintext);
+
+        let mut decrypted = Vec::new();
+        cbc::decrypt_stream(Cursor::new(&encrypted), &mut decrypted, &cipher).unwrap();
+        assert_eq!(decrypted, plaintext);
+    }
+
+    #[tokio::test]
+    async fn test_reader_rejects_truncated_stream() {
+        let cipher = Aes256::new(&Key::new([6; 32]));
+        let mut encrypted = Vec::new();
+        cbc::encrypt_stream(Cursor::new(&[1u8; 50]), &mut encrypted, &cipher).unwrap();
+
+        let truncated = &encrypted[..encrypted.len() - 3];
+        let mut reader = AsyncDecryptingReader::new(truncated, &cipher);
+        let error = reader.read_to_end(&mut Vec::new()).await.unwrap_err();
+        assert!(matches!(Error::from(error), Error::TruncatedInput));
+        assert!(reader.read(&mut [0u8; 4]).await.is_err());
+
+        let mut reader = AsyncDecryptingReader::new(&encrypted[..5], &cipher);
+        let error = reader.read(&mut [0u8; 4]).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,369 @@
+// This file holds the bitsliced backend, which runs the AES round functions on eight blocks at
+// once using nothing but bitwise operations on u64 words.
+//
+// The blocks are processed in two groups of four. A group is stored as eight bit planes: plane
+// `b` holds bit `b` of every byte of the four blocks, with byte `p` of block `k` at bit
+// `4 * p + k`. Each byte position is therefore a nibble and each state column a 16-bit lane, so
+// ShiftRows and MixColumns become fixed shifts and masks, and SubBytes becomes a circuit over the
+// planes. Nothing branches on or indexes by the data.
+
+use std::fmt;
+
+use zeroize::Zeroize;
+
+use crate::key::KeySchedule;
+
+/// The number of blocks processed by each call.
+pub const BLOCKS: usize = 8;
+
+const GROUP: usize = 4;
+
+pub(crate) type Planes = [u64; 8];
+
+/// A key schedule with every round key spread across the planes of a group, built once per
+/// cipher and wiped when dropped.
+#[derive(Clone)]
+pub(crate) struct BitslicedSchedule {
+    round_keys: [Planes; 15],
+    rounds: usize,
+}
+
+impl BitslicedSchedule {
+    pub(crate) fn new(schedule: &KeySchedule) -> Self {
+        let mut round_keys = [[0; 8]; 15];
+        for (round, planes) in round_keys
+            .iter_mut()
+            .enumerate()
+            .take(schedule.rounds() + 1)
+        {
+            let mut bytes = [0u8; 16];
+            for (i, chunk) in bytes.chunks_exact_mut(4).enumerate() {
+                chunk.copy_from_slice(&schedule[4 * round + i].to_be_bytes());
+            }
+            *planes = pack_broadcast(&bytes);
+            bytes.zeroize();
+        }
+
+        BitslicedSchedule {
+            round_keys,
+            rounds: schedule.rounds(),
+        }
+    }
+}
+
+impl Drop for BitslicedSchedule {
+    fn drop(&mut self) {
+        self.round_keys.zeroize();
+    }
+}
+
+impl fmt::Debug for BitslicedSchedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "BitslicedSchedule {{ rounds: {}, round_keys: <redacted> }}",
+            self.rounds
+        )
+    }
+}
+
+/// Encrypts eight blocks in place with a schedule built from the forward key expansion.
+pub(crate) fn encrypt(schedule: &BitslicedSchedule, blocks: &mut [[u8; 16]; BLOCKS]) {
+    let nr = schedule.rounds;
+    for group in blocks.chunks_exact_mut(GROUP) {
+        let mut state = pack(group);
+
+        add_round_key(&mut state, &schedule.round_keys[0]);
+        for round in 1..nr {
+            sub_bytes(&mut state);
+            shift_rows(&mut state);
+            mix_columns(&mut state);
+            add_round_key(&mut state, &schedule.round_keys[round]);
+        }
+        sub_bytes(&mut state);
+        shift_rows(&mut state);
+        add_round_key(&mut state, &schedule.round_keys[nr]);
+
+        unpack(&state, group);
+    }
+}
+
+/// Decrypts eight blocks in place with the equivalent inverse cipher, using a schedule built from
+/// the inverse key expansion.
+pub(crate) fn decrypt(schedule: &BitslicedSchedule, blocks: &mut [[u8; 16]; BLOCKS]) {
+    let nr = schedule.rounds;
+    for group in blocks.chunks_exact_mut(GROUP) {
+        let mut state = pack(group);
+
+        add_round_key(&mut state, &schedule.round_keys[nr]);
+        for round in (1..nr).rev() {
+            inverse_sub_bytes(&mut state);
+            inverse_shift_rows(&mut state);
+            inverse_mix_columns(&mut state);
+            add_round_key(&mut state, &schedule.round_keys[round]);
+        }
+        inverse_sub_bytes(&mut state);
+        inverse_shift_rows(&mut state);
+        add_round_key(&mut state, &schedule.round_keys[0]);
+
+        unpack(&state, group);
+    }
+}
+
+/// Spreads four blocks across the planes of a group.
+fn pack(blocks: &[[u8; 16]]) -> Planes {
+    let mut planes = [0; 8];
+    for (k, block) in blocks.iter().enumerate() {
+        for (p, &byte) in block.iter().enumerate() {
+            for (b, plane) in planes.iter_mut().enumerate() {
+                *plane |= u64::from((byte >> b) & 1) << (4 * p + k);
+            }
+        }
+    }
+    planes
+}
+
+/// Spreads a single block across the planes as if it had been packed into all four slots.
+fn pack_broadcast(block: &[u8; 16]) -> Planes {
+    let mut planes = [0; 8];
+    for (p, &byte) in block.iter().enumerate() {
+        for (b, plane) in planes.iter_mut().enumerate() {
+            *plane |= (u64::from((byte >> b) & 1) * 0xF) << (4 * p);
+        }
+    }
+    planes
+}
+
+/// The inverse of [`pack`].
+fn unpack(planes: &Planes, blocks: &mut [[u8; 16]]) {
+    for (k, block) in blocks.iter_mut().enumerate() {
+        for (p, byte) in block.iter_mut().enumerate() {
+            *byte = 0;
+            for (b, plane) in planes.iter().enumerate() {
+                *byte |= (((plane >> (4 * p + k)) & 1) as u8) << b;
+            }
+        }
+    }
+}
+
+fn add_round_key(state: &mut Planes, round_key: &Planes) {
+    for (plane, key) in state.iter_mut().zip(round_key) {
+        *plane ^= key;
+    }
+}
+
+/// Applies the S-box to every byte held in the planes. Each bit position is independent, so this
+/// works for any packing, including the scalar one in the `sbox` module.
+pub(crate) fn sub_bytes(state: &mut Planes) {
+    *state = affine(&gf_inverse(state));
+}
+
+/// Applies the inverse S-box to every byte held in the planes.
+pub(crate) fn inverse_sub_bytes(state: &mut Planes) {
+    *state = gf_inverse(&inverse_affine(state));
+}
+
+/// Reduces a product of two polynomials of degree 7 modulo x^8 + x^4 + x^3 + x + 1.
+fn reduce(mut product: [u64; 15]) -> Planes {
+    for k in (8..15).rev() {
+        let high = product[k];
+        product[k - 4] ^= high;
+        product[k - 5] ^= high;
+        product[k - 7] ^= high;
+        product[k - 8] ^= high;
+    }
+    product[..8].try_into().unwrap()
+}
+
+/// Multiplies every byte of `a` by the matching byte of `b` in GF(2^8).
+fn gf_mul(a: &Planes, b: &Planes) -> Planes {
+    let mut product = [0; 15];
+    for (i, a_plane) in a.iter().enumerate() {
+        for (j, b_plane) in b.iter().enumerate() {
+            product[i + j] ^= a_plane & b_plane;
+        }
+    }
+    reduce(product)
+}
+
+/// Squares every byte in GF(2^8). Squaring is linear, so it only moves bits around.
+fn gf_square(a: &Planes) -> Planes {
+    let mut product = [0; 15];
+    for (i, plane) in a.iter().enumerate() {
+        product[2 * i] = *plane;
+    }
+    reduce(product)
+}
+
+/// Raises every byte to the power 254, the same fixed chain as the scalar S-box.
+fn gf_inverse(x: &Planes) -> Planes {
+    let x2 = gf_square(x);
+    let x3 = gf_mul(&x2, x);
+    let x6 = gf_square(&x3);
+    let x12 = gf_square(&x6);
+    let x15 = gf_mul(&x12, &x3);
+    let x240 = gf_square(&gf_square(&gf_square(&gf_square(&x15))));
+    let x14 = gf_mul(&x12, &x2);
+    gf_mul(&x240, &x14)
+}
+
+/// The S-box affine transform, applied plane by plane.
+fn affine(b: &Planes) -> Planes {
+    let mut out = [0; 8];
+    for (i, plane) in out.iter_mut().enumerate() {
+        *plane = b[i] ^ b[(i + 4) % 8] ^ b[(i + 5) % 8] ^ b[(i + 6) % 8] ^ b[(i + 7) % 8];
+        if (0x63 >> i) & 1 == 1 {
+            *plane = !*plane;
+        }
+    }
+    out
+}
+
+/// The inverse of [`affine`].
+fn inverse_affine(b: &Planes) -> Planes {
+    let mut out = [0; 8];
+    for (i, plane) in out.iter_mut().enumerate() {
+        *plane = b[(i + 2) % 8] ^ b[(i + 5) % 8] ^ b[(i + 7) % 8];
+        if (0x05 >> i) & 1 == 1 {
+            *plane = !*plane;
+        }
+    }
+    out
+}
+
+/// The nibbles of row 0 in every column; row `r` is this shifted left by `4 * r`.
+const ROW_MASK: u64 = 0x000F_000F_000F_000F;
+
+/// Row `r` moves left by `r` columns, which is a rotation of its nibbles by `16 * r` bits.
+fn shift_rows(state: &mut Planes) {
+    for plane in state.iter_mut() {
+        *plane = (0..4).fold(0, |out, r| {
+            out | (*plane & (ROW_MASK << (4 * r))).rotate_right(16 * r)
+        });
+    }
+}
+
+fn inverse_shift_rows(state: &mut Planes) {
+    for plane in state.iter_mut() {
+        *plane = (0..4).fold(0, |out, r| {
+            out | (*plane & (ROW_MASK << (4 * r))).rotate_left(16 * r)
+        });
+    }
+}
+
+/// Moves row `r + k` of every column into row `r`, wrapping within the column.
+fn rotate_rows(plane: u64, k: u32) -> u64 {
+    let low = 0x0001_0001_0001_0001 * (0xFFFF >> (4 * k));
+    ((plane >> (4 * k)) & low) | ((plane << (16 - 4 * k)) & !low)
+}
+
+/// Multiplies every byte by x (that is, by 2) in GF(2^8).
+fn xtime(a: &Planes) -> Planes {
+    [
+        a[7],
+        a[0] ^ a[7],
+        a[1],
+        a[2] ^ a[7],
+        a[3] ^ a[7],
+        a[4],
+        a[5],
+        a[6],
+    ]
+}
+
+/// Computes `2 * a[r] ^ 3 * a[r + 1] ^ a[r + 2] ^ a[r + 3]` for every row, written as
+/// `2 * (a[r] ^ a[r + 1]) ^ a[r + 1] ^ a[r + 2] ^ a[r + 3]`.
+fn mix_columns(state: &mut Planes) {
+    let rotated = |k| state.map(|plane| rotate_rows(plane, k));
+    let (r1, r2, r3) = (rotated(1), rotated(2), rotated(3));
+
+    let mut sum = *state;
+    add_round_key(&mut sum, &r1);
+    let doubled = xtime(&sum);
+
+    for i in 0..8 {
+        state[i] = doubled[i] ^ r1[i] ^ r2[i] ^ r3[i];
+    }
+}
+
+/// InvMixColumns factors into adding `4 * (a[r] ^ a[r + 2])` to every row, followed by
+/// MixColumns.
+fn inverse_mix_columns(state: &mut Planes) {
+    let mut sum = state.map(|plane| rotate_rows(plane, 2));
+    add_round_key(&mut sum, state);
+    add_round_key(state, &xtime(&xtime(&sum)));
+    mix_columns(state);
+}
+
+#[cfg(test)]
+mod test {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+    use crate::cipher::{Aes128, Aes192, Aes256};
+    use crate::key::Key;
+    use crate::test_util::hex_array;
+
+    fn random_blocks(rng: &mut StdRng) -> [[u8; 16]; BLOCKS] {
+        let mut blocks = [[0; 16]; BLOCKS];
+        for block in blocks.iter_mut() {
+            rng.fill(block);
+        }
+        blocks
+    }
+
+    #[test]
+    fn test_pack_round_trip() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let blocks = random_blocks(&mut rng);
+
+        let mut unpacked = [[0; 16]; GROUP];
+        unpack(&pack(&blocks[..GROUP]), &mut unpacked);
+        assert_eq!(unpacked, blocks[..GROUP]);
+    }
+
+    #[test]
+    fn test_fips_197_vector_in_every_slot() {
+        let cipher = Aes128::new(&Key::new(hex_array("000102030405060708090a0b0c0d0e0f")));
+        let plaintext = hex_array("00112233445566778899aabbccddeeff");
+        let ciphertext = hex_array("69c4e0d86a7b0430d8cdb78070b4c55a");
+
+        let mut blocks = [plaintext; BLOCKS];
+        cipher.encrypt_8_blocks(&mut blocks);
+        assert_eq!(blocks, [ciphertext; BLOCKS]);
+
+        cipher.decrypt_8_blocks(&mut blocks);
+        assert_eq!(blocks, [plaintext; BLOCKS]);
+    }
+
+    macro_rules! cross_check {
+        ($test:ident, $cipher:ident, $key_len:literal) => {
+            #[test]
+            fn $test() {
+                let mut rng = StdRng::seed_from_u64($key_len);
+
+                for _ in 0..20 {
+                    let mut key = [0; $key_len];
+                    rng.fill(&mut key);
+                    let cipher = $cipher::new(&Key::new(key));
+                    // `new` may pick AES-NI, so compare against the software rounds explicitly.
+                    let reference = $cipher::software(&Key::new(key));
+                    let plaintext = random_blocks(&mut rng);
+
+                    let mut blocks = plaintext;
+                    cipher.encrypt_8_blocks(&mut blocks);
+                    for (block, original) in blocks.iter().zip(&plaintext) {
+                        assert_eq!(*block, reference.encrypt_block(original));
+                    }
+
+                    cipher.decrypt_8_blocks(&mut blocks);
+                    assert_eq!(blocks, plaintext);
+                }
+            }
+        };
+    }
+
+    cross_check!(test_matches_reference_aes128, Aes128, 16);
+    cross_check!(test_matches_reference_aes192, Aes192, 24);
+    cross_check!(test_matches_reference_aes256, Aes256, 32);
+}
@@ -0,0 +1,574 @@
+// This file holds the cipher block chaining (CBC) mode with PKCS#7 padding.
+
+use std::io::{Read, Write};
+
+use crate::cipher::BlockCipher;
+use crate::error::{Error, Result};
+use crate::io_util::read_full;
+use crate::shared::xor_block;
+
+const BLOCK_SIZE: usize = 16;
+
+/// The version written as the first byte of every stream, ahead of the IV.
+pub const FORMAT_VERSION: u8 = 1;
+
+/// The length of the stream header: the version byte followed by the IV.
+pub const HEADER_SIZE: usize = 1 + BLOCK_SIZE;
+
+/// How much ciphertext the stream functions read at a time.
+pub(crate) const STREAM_CHUNK_SIZE: usize = 64 * BLOCK_SIZE;
+
+/// Encrypts a sequence of blocks, chaining each one into the next.
+pub struct CbcEncryptor<C> {
+    cipher: C,
+    chain: [u8; BLOCK_SIZE],
+}
+
+impl<C: BlockCipher> CbcEncryptor<C> {
+    pub fn new(cipher: C, iv: &[u8; BLOCK_SIZE]) -> Self {
+        CbcEncryptor { cipher, chain: *iv }
+    }
+
+    /// Encrypts the next plaintext block.
+    pub fn encrypt_block(&mut self, block: &[u8; BLOCK_SIZE]) -> [u8; BLOCK_SIZE] {
+        let mut input = *block;
+        xor_block(&mut input, &self.chain);
+        self.chain = self.cipher.encrypt_block(&input);
+        self.chain
+    }
+}
+
+/// Decrypts a sequence of blocks produced by [`CbcEncryptor`].
+pub struct CbcDecryptor<C> {
+    cipher: C,
+    chain: [u8; BLOCK_SIZE],
+}
+
+impl<C: BlockCipher> CbcDecryptor<C> {
+    pub fn new(cipher: C, iv: &[u8; BLOCK_SIZE]) -> Self {
+        CbcDecryptor { cipher, chain: *iv }
+    }
+
+    /// Decrypts the next ciphertext block.
+    pub fn decrypt_block(&mut self, block: &[u8; BLOCK_SIZE]) -> [u8; BLOCK_SIZE] {
+        let mut output = self.cipher.decrypt_block(block);
+        xor_block(&mut output, &self.chain);
+        self.chain = *block;
+        output
+    }
+}
+
+/// Encrypts plaintext with PKCS#7 padding as it arrives in arbitrarily sized pieces.
+///
+/// Whole blocks are encrypted as soon as they are complete; the padding is only added by
+/// [`PaddedEncryptor::finish`].
+pub struct PaddedEncryptor<C> {
+    encryptor: CbcEncryptor<C>,
+    partial: [u8; BLOCK_SIZE],
+    partial_len: usize,
+}
+
+impl<C: BlockCipher> PaddedEncryptor<C> {
+    pub fn new(cipher: C, iv: &[u8; BLOCK_SIZE]) -> Self {
+        PaddedEncryptor {
+            encryptor: CbcEncryptor::new(cipher, iv),
+            partial: [0; BLOCK_SIZE],
+            partial_len: 0,
+        }
+    }
+
+    /// Encrypts as much of `input` as possible, appending the ciphertext to `output`.
+    pub fn update(&mut self, mut input: &[u8], output: &mut Vec<u8>) {
+        while !input.is_empty() {
+            let take = (BLOCK_SIZE - self.partial_len).min(input.len());
+            self.partial[self.partial_len..self.partial_len + take].copy_from_slice(&input[..take]);
+            self.partial_len += take;
+            input = &input[take..];
+
+            if self.partial_len == BLOCK_SIZE {
+                output.extend_from_slice(&self.encryptor.encrypt_block(&self.partial));
+                self.partial_len = 0;
+            }
+        }
+    }
+
+    /// Pads and encrypts the remaining plaintext, appending the final block to `output`.
+    pub fn finish(mut self, output: &mut Vec<u8>) {
+        let padded = pkcs7_pad(&self.partial[..self.partial_len]);
+        output.extend_from_slice(&self.encryptor.encrypt_block(padded[..].try_into().unwrap()));
+    }
+}
+
+/// Decrypts padded CBC ciphertext as it arrives in arbitrarily sized pieces.
+///
+/// Everything except the most recent block is released immediately; that block is held back
+/// until [`PaddedDecryptor::finish`] because it is the only one that can carry padding. Memory use
+/// is therefore constant no matter how long the ciphertext is.
+pub struct PaddedDecryptor<C> {
+    decryptor: CbcDecryptor<C>,
+    partial: [u8; BLOCK_SIZE],
+    partial_len: usize,
+    pending: Option<[u8; BLOCK_SIZE]>,
+}
+
+impl<C: BlockCipher> PaddedDecryptor<C> {
+    pub fn new(cipher: C, iv: &[u8; BLOCK_SIZE]) -> Self {
+        PaddedDecryptor {
+            decryptor: CbcDecryptor::new(cipher, iv),
+            partial: [0; BLOCK_SIZE],
+            partial_len: 0,
+            pending: None,
+        }
+    }
+
+    /// Decrypts as much of `input` as possible, appending the released plaintext to `output`.
+    pub fn update(&mut self, mut input: &[u8], output: &mut Vec<u8>) {
+        while !input.is_empty() {
+            let take = (BLOCK_SIZE - self.partial_len).min(input.len());
+            self.partial[self.partial_len..self.partial_len + take].copy_from_slice(&input[..take]);
+            self.partial_len += take;
+            input = &input[take..];
+
+            if self.partial_len == BLOCK_SIZE {
+                let block = self.decryptor.decrypt_block(&self.partial);
+                if let Some(released) = self.pending.replace(block) {
+                    output.extend_from_slice(&released);
+                }
+                self.partial_len = 0;
+            }
+        }
+    }
+
+    /// Checks and strips the padding from the held back block, appending what remains to `output`.
+    pub fn finish(self, output: &mut Vec<u8>) -> Result<()> {
+        if self.partial_len != 0 {
+            return Err(Error::TruncatedInput);
+        }
+
+        let last = self.pending.ok_or(Error::TruncatedInput)?;
+        let kept = pkcs7_unpad(&last)?;
+        output.extend_from_slice(&last[..kept]);
+
+        Ok(())
+    }
+}
+
+/// Generates a random initialization vector.
+pub fn generate_iv() -> [u8; BLOCK_SIZE] {
+    rand::random()
+}
+
+/// Pads `data` to a whole number of blocks. A full block of padding is added when `data` is
+/// already block aligned, so the padding can always be removed unambiguously.
+pub fn pkcs7_pad(data: &[u8]) -> Vec<u8> {
+    let pad = BLOCK_SIZE - data.len() % BLOCK_SIZE;
+    let mut output = Vec::with_capacity(data.len() + pad);
+    output.extend_from_slice(data);
+    output.resize(data.len() + pad, pad as u8);
+    output
+}
+
+/// Validates the padding on the final block and returns how many of its bytes are plaintext.
+///
+/// Every byte of the block is inspected regardless of where the padding is wrong, so the time
+/// taken does not reveal which byte failed the check.
+pub fn pkcs7_unpad(block: &[u8; BLOCK_SIZE]) -> Result<usize> {
+    let pad = block[BLOCK_SIZE - 1];
+    let mut invalid = ct_less_than(pad, 1) | ct_less_than(BLOCK_SIZE as u8, pad);
+
+    for (i, byte) in block.iter().enumerate() {
+        let in_padding = ct_less_than((BLOCK_SIZE - 1 - i) as u8, pad);
+        invalid |= in_padding & (byte ^ pad);
+    }
+
+    if invalid != 0 {
+        return Err(Error::BadPadding);
+    }
+
+    Ok(BLOCK_SIZE - pad as usize)
+}
+
+/// Encrypts `plaintext` with PKCS#7 padding.
+pub fn encrypt<C: BlockCipher>(cipher: C, iv: &[u8; BLOCK_SIZE], plaintext: &[u8]) -> Vec<u8> {
+    let mut encryptor = CbcEncryptor::new(cipher, iv);
+    let mut output = pkcs7_pad(plaintext);
+
+    for chunk in output.chunks_exact_mut(BLOCK_SIZE) {
+        let block: &mut [u8; BLOCK_SIZE] = chunk.try_into().unwrap();
+        *block = encryptor.encrypt_block(block);
+    }
+
+    output
+}
+
+/// Decrypts `ciphertext` and strips its PKCS#7 padding.
+pub fn decrypt<C: BlockCipher>(
+    cipher: C,
+    iv: &[u8; BLOCK_SIZE],
+    ciphertext: &[u8],
+) -> Result<Vec<u8>> {
+    if ciphertext.is_empty() || !ciphertext.len().is_multiple_of(BLOCK_SIZE) {
+        return Err(Error::TruncatedInput);
+    }
+
+    let mut decryptor = CbcDecryptor::new(cipher, iv);
+    let mut output = ciphertext.to_vec();
+
+    for chunk in output.chunks_exact_mut(BLOCK_SIZE) {
+        let block: &mut [u8; BLOCK_SIZE] = chunk.try_into().unwrap();
+        *block = decryptor.decrypt_block(block);
+    }
+
+    let last: &[u8; BLOCK_SIZE] = output[output.len() - BLOCK_SIZE..].try_into().unwrap();
+    let kept = pkcs7_unpad(last)?;
+    output.truncate(output.len() - BLOCK_SIZE + kept);
+
+    Ok(output)
+}
+
+/// Builds the stream header for `iv`.
+pub(crate) fn stream_header(iv: &[u8; BLOCK_SIZE]) -> [u8; HEADER_SIZE] {
+    let mut header = [FORMAT_VERSION; HEADER_SIZE];
+    header[1..].copy_from_slice(iv);
+    header
+}
+
+/// Checks the version in a stream header and returns the IV that follows it.
+pub(crate) fn parse_stream_header(header: &[u8; HEADER_SIZE]) -> Result<[u8; BLOCK_SIZE]> {
+    if header[0] != FORMAT_VERSION {
+        return Err(Error::UnsupportedVersion(header[0]));
+    }
+    Ok(header[1..].try_into().unwrap())
+}
+
+/// Encrypts the input stream under a random IV, writing a header holding [`FORMAT_VERSION`] and
+/// the IV followed by the padded ciphertext to the output stream.
+pub fn encrypt_stream<R: Read, W: Write, C: BlockCipher>(
+    mut reader: R,
+    mut writer: W,
+    cipher: C,
+) -> Result<()> {
+    let iv = generate_iv();
+    let mut encryptor = PaddedEncryptor::new(cipher, &iv);
+    let mut buffer = [0u8; STREAM_CHUNK_SIZE];
+    let mut output = Vec::with_capacity(STREAM_CHUNK_SIZE);
+
+    writer.write_all(&stream_header(&iv))?;
+
+    loop {
+        let read_size = read_full(&mut reader, &mut buffer)?;
+        if read_size == 0 {
+            break;
+        }
+
+        encryptor.update(&buffer[..read_size], &mut output);
+        writer.write_all(&output)?;
+        output.clear();
+    }
+
+    encryptor.finish(&mut output);
+    writer.write_all(&output)?;
+
+    Ok(())
+}
+
+/// Decrypts a stream produced by [`encrypt_stream`] using constant memory.
+///
+/// Fails with [`Error::UnsupportedVersion`] if the stream was written in a format this version of
+/// the crate does not understand.
+pub fn decrypt_stream<R: Read, W: Write, C: BlockCipher>(
+    mut reader: R,
+    mut writer: W,
+    cipher: C,
+) -> Result<()> {
+    let mut header = [0u8; HEADER_SIZE];
+    if read_full(&mut reader, &mut header)? < HEADER_SIZE {
+        return Err(Error::TruncatedInput);
+    }
+    let iv = parse_stream_header(&header)?;
+
+    let mut decryptor = PaddedDecryptor::new(cipher, &iv);
+    let mut buffer = [0u8; STREAM_CHUNK_SIZE];
+    let mut output = Vec::with_capacity(STREAM_CHUNK_SIZE);
+
+    loop {
+        let read_size = read_full(&mut reader, &mut buffer)?;
+        if read_size == 0 {
+            break;
+        }
+
+        decryptor.update(&buffer[..read_size], &mut output);
+        writer.write_all(&output)?;
+        output.clear();
+    }
+
+    decryptor.finish(&mut output)?;
+    writer.write_all(&output)?;
+
+    Ok(())
+}
+
+/// Returns 0xFF when `a < b` and 0x00 otherwise, without branching on either value.
+fn ct_less_than(a: u8, b: u8) -> u8 {
+    ((a as u16).wrapping_sub(b as u16) >> 8) as u8
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::cipher::{Aes128, Aes256};
+    use crate::key::Key;
+    use crate::test_util::{hex, hex_array, ChunkedReader};
+
+    const SP800_38A_PLAINTEXT: &str = "
+        6bc1bee22e409f96e93d7e117393172a ae2d8a571e03ac9c9eb76fac45af8e51
+        30c81c46a35ce411e5fbc1191a0a52ef f69f2445df4f9b17ad2b417be66c3710";
+
+    fn encrypt_unpadded<C: BlockCipher>(cipher: C, iv: &[u8; 16], data: &[u8]) -> Vec<u8> {
+        let mut encryptor = CbcEncryptor::new(cipher, iv);
+        data.chunks(16)
+            .flat_map(|block| encryptor.encrypt_block(block.try_into().unwrap()))
+            .collect()
+    }
+
+    fn decrypt_unpadded<C: BlockCipher>(cipher: C, iv: &[u8; 16], data: &[u8]) -> Vec<u8> {
+        let mut decryptor = CbcDecryptor::new(cipher, iv);
+        data.chunks(16)
+            .flat_map(|block| decryptor.decrypt_block(block.try_into().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn test_sp800_38a_cbc_aes128() {
+        let cipher = Aes128::new(&Key::new(hex_array("2b7e151628aed2a6abf7158809cf4f3c")));
+        let iv = hex_array("000102030405060708090a0b0c0d0e0f");
+        let plaintext = hex(SP800_38A_PLAINTEXT);
+        let ciphertext = hex(
+            "7649abac8119b246cee98e9b12e9197d 5086cb9b507219ee95db113a917678b2
+             73bed6b8e3c1743b7116e69e22229516 3ff1caa1681fac09120eca307586e1a7",
+        );
+
+        assert_eq!(encrypt_unpadded(&cipher, &iv, &plaintext), ciphertext);
+        assert_eq!(decrypt_unpadded(&cipher, &iv, &ciphertext), plaintext);
+    }
+
+    #[test]
+    fn test_sp800_38a_cbc_aes256() {
+        let cipher = Aes256::new(&Key::new(hex_array(
+            "603deb1015ca71be2b73aef0857d77811f352c073b6108d72d9810a30914dff4",
+        )));
+        let iv = hex_array("000102030405060708090a0b0c0d0e0f");
+        let plaintext = hex(SP800_38A_PLAINTEXT);
+        let ciphertext = hex(
+            "f58c4c04d6e5f1ba779eabfb5f7bfbd6 9cfc4e967edb808d679f777bc6702c7d
+             39f23369a9d9bacfa530e26304231461 b2eb05e2c39be9fcda6c19078c6a9d1b",
+        );
+
+        assert_eq!(encrypt_unpadded(&cipher, &iv, &plaintext), ciphertext);
+        assert_eq!(decrypt_unpadded(&cipher, &iv, &ciphertext), plaintext);
+
+        // The padded form is the same ciphertext followed by one block of padding.
+        let padded = encrypt(&cipher, &iv, &plaintext);
+        assert_eq!(padded.len(), ciphertext.len() + 16);
+        assert_eq!(padded[..ciphertext.len()], ciphertext[..]);
+        assert_eq!(decrypt(&cipher, &iv, &padded).unwrap(), plaintext);
+    }
+
+    #[test]
+    fn test_pkcs7_pad() {
+        assert_eq!(pkcs7_pad(&[]), [16; 16]);
+        assert_eq!(pkcs7_pad(&[1, 2, 3])[3..], [13; 13]);
+        assert_eq!(pkcs7_pad(&[0; 16])[16..], [16; 16]);
+        assert_eq!(pkcs7_pad(&[0; 31]).len(), 32);
+    }
+
+    #[test]
+    fn test_pkcs7_unpad() {
+        let mut block = [0xaa; 16];
+        block[13..].copy_from_slice(&[3, 3, 3]);
+        assert_eq!(pkcs7_unpad(&block).unwrap(), 13);
+        assert_eq!(pkcs7_unpad(&[16; 16]).unwrap(), 0);
+
+        // Zero, oversized and inconsistent padding are all rejected.
+        block[15] = 0;
+        assert!(matches!(pkcs7_unpad(&block), Err(Error::BadPadding)));
+        block[15] = 17;
+        assert!(pkcs7_unpad(&block).is_err());
+        block[13..].copy_from_slice(&[2, 3, 3]);
+        assert!(pkcs7_unpad(&block).is_err());
+        let mut block = [16; 16];
+        block[0] = 15;
+        assert!(pkcs7_unpad(&block).is_err());
+    }
+
+    #[test]
+    fn test_decrypt_rejects_bad_input() {
+        let cipher = Aes256::new(&Key::new([9; 32]));
+        let iv = [0; 16];
+
+        assert!(matches!(
+            decrypt(&cipher, &iv, &[]),
+            Err(Error::TruncatedInput)
+        ));
+        assert!(matches!(
+            decrypt(&cipher, &iv, &[0; 17]),
+            Err(Error::TruncatedInput)
+        ));
+
+        let mut ciphertext = encrypt(&cipher, &iv, b"attack at dawn");
+        ciphertext[15] ^= 0x01;
+        assert!(matches!(
+            decrypt(&cipher, &iv, &ciphertext),
+            Err(Error::BadPadding)
+        ));
+    }
+
+    #[test]
+    fn test_stream_round_trip() {
+        let cipher = Aes256::new(&Key::new([3; 32]));
+
+        for len in [0, 1, 15, 16, 17, 64, 1000] {
+            let plaintext: Vec<u8> = (0..len).map(|i| i as u8).collect();
+
+            let mut encrypted = Vec::new();
+            encrypt_stream(Cursor::new(&plaintext), &mut encrypted, &cipher).unwrap();
+            assert_eq!(encrypted.len(), HEADER_SIZE + (len / 16 + 1) * 16);
+            assert_eq!(encrypted[0], FORMAT_VERSION);
+
+            let iv: [u8; 16] = encrypted[1..HEADER_SIZE].try_into().unwrap();
+            assert_eq!(
+                decrypt(&cipher, &iv, &encrypted[HEADER_SIZE..]).unwrap(),
+                plaintext
+            );
+
+            let mut decrypted = Vec::new();
+            decrypt_stream(Cursor::new(&encrypted), &mut decrypted, &cipher).unwrap();
+            assert_eq!(decrypted, plaintext);
+        }
+    }
+
+    #[test]
+    fn test_padded_encryptor_matches_one_shot() {
+        let cipher = Aes256::new(&Key::new([3; 32]));
+        let iv = [4; 16];
+        let plaintext: Vec<u8> = (0..40).collect();
+
+        let mut encryptor = PaddedEncryptor::new(&cipher, &iv);
+        let mut output = Vec::new();
+
+        encryptor.update(&plaintext[..10], &mut output);
+        assert!(output.is_empty());
+        encryptor.update(&plaintext[10..33], &mut output);
+        assert_eq!(output.len(), 32);
+        encryptor.update(&plaintext[33..], &mut output);
+        encryptor.finish(&mut output);
+
+        assert_eq!(output, encrypt(&cipher, &iv, &plaintext));
+    }
+
+    #[test]
+    fn test_padded_decryptor_holds_back_one_block() {
+        let cipher = Aes256::new(&Key::new([3; 32]));
+        let iv = [4; 16];
+        let plaintext: Vec<u8> = (0..40).collect();
+        let ciphertext = encrypt(&cipher, &iv, &plaintext);
+
+        let mut decryptor = PaddedDecryptor::new(&cipher, &iv);
+        let mut output = Vec::new();
+
+        decryptor.update(&ciphertext[..20], &mut output);
+        assert!(output.is_empty());
+        decryptor.update(&ciphertext[20..33], &mut output);
+        assert_eq!(output, plaintext[..16]);
+        decryptor.update(&ciphertext[33..], &mut output);
+        assert_eq!(output, plaintext[..32]);
+
+        decryptor.finish(&mut output).unwrap();
+        assert_eq!(output, plaintext);
+    }
+
+    #[test]
+    fn test_padded_decryptor_rejects_partial_block() {
+        let cipher = Aes256::new(&Key::new([3; 32]));
+        let ciphertext = encrypt(&cipher, &[4; 16], b"some plaintext");
+
+        let mut decryptor = PaddedDecryptor::new(&cipher, &[4; 16]);
+        decryptor.update(&ciphertext[..15], &mut Vec::new());
+        assert!(matches!(
+            decryptor.finish(&mut Vec::new()),
+            Err(Error::TruncatedInput)
+        ));
+    }
+
+    #[test]
+    fn test_stream_short_reads() {
+        let cipher = Aes256::new(&Key::new([3; 32]));
+        let plaintext: Vec<u8> = (0..300).map(|i| (i * 5) as u8).collect();
+
+        for size in [1, 7, 16, 33] {
+            let mut encrypted = Vec::new();
+            encrypt_stream(
+                ChunkedReader::fixed(&plaintext, size),
+                &mut encrypted,
+                &cipher,
+            )
+            .unwrap();
+            let iv: [u8; 16] = encrypted[1..HEADER_SIZE].try_into().unwrap();
+            assert_eq!(
+                decrypt(&cipher, &iv, &encrypted[HEADER_SIZE..]).unwrap(),
+                plaintext
+            );
+
+            let mut decrypted = Vec::new();
+            let reader = ChunkedReader::random(&encrypted, size as u64).interrupting();
+            decrypt_stream(reader, &mut decrypted, &cipher).unwrap();
+            assert_eq!(decrypted, plaintext);
+        }
+    }
+
+    #[test]
+    fn test_stream_uses_fresh_iv() {
+        let cipher = Aes256::new(&Key::new([3; 32]));
+        let mut first = Vec::new();
+        let mut second = Vec::new();
+
+        encrypt_stream(Cursor::new(b"same message"), &mut first, &cipher).unwrap();
+        encrypt_stream(Cursor::new(b"same message"), &mut second, &cipher).unwrap();
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn test_decrypt_stream_rejects_bad_input() {
+        let cipher = Aes256::new(&Key::new([3; 32]));
+        let mut sink = Vec::new();
+
+        let mut header = [FORMAT_VERSION; HEADER_SIZE];
+        assert!(matches!(
+            decrypt_stream(Cursor::new(&header[..8]), &mut sink, &cipher),
+            Err(Error::TruncatedInput)
+        ));
+        assert!(matches!(
+            decrypt_stream(Cursor::new(&header), &mut sink, &cipher),
+            Err(Error::TruncatedInput)
+        ));
+        assert!(matches!(
+            decrypt_stream(Cursor::new(&[FORMAT_VERSION; 40]), &mut sink, &cipher),
+            Err(Error::TruncatedInput)
+        ));
+
+        let mut encrypted = Vec::new();
+        encrypt_stream(Cursor::new(b"from the future"), &mut encrypted, &cipher).unwrap();
+        encrypted[0] = 2;
+        assert!(matches!(
+            decrypt_stream(Cursor::new(&encrypted), &mut sink, &cipher),
+            Err(Error::UnsupportedVersion(2))
+        ));
+        header[0] = 0;
+        assert!(matches!(
+            decrypt_stream(Cursor::new(&header), &mut sink, &cipher),
+            Err(Error::UnsupportedVersion(0))
+        ));
+    }
+}
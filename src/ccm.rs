@@ -0,0 +1,322 @@
+// This file holds the Counter with CBC-MAC (CCM) authenticated encryption from NIST SP 800-38C.
+
+use crate::cipher::BlockCipher;
+use crate::ctr;
+use crate::error::{Error, Result};
+use crate::gcm::constant_time_eq;
+use crate::shared::xor_block;
+
+const BLOCK_SIZE: usize = 16;
+
+/// The tag lengths, in bytes, permitted by SP 800-38C.
+const TAG_LENGTHS: [usize; 7] = [4, 6, 8, 10, 12, 14, 16];
+
+/// The nonce lengths, in bytes, permitted by SP 800-38C.
+const NONCE_LENGTHS: std::ops::RangeInclusive<usize> = 7..=13;
+
+/// CCM authenticated encryption over a block cipher.
+///
+/// Nonces may be 7 to 13 bytes long. The remaining `15 - nonce_len` bytes of each counter block
+/// hold the message length, so shorter nonces allow longer messages: a 13-byte nonce limits the
+/// plaintext to 65535 bytes. Sealed messages are the ciphertext followed by the tag.
+pub struct Ccm<C> {
+    cipher: C,
+    tag_len: usize,
+}
+
+impl<C: BlockCipher> Ccm<C> {
+    /// Creates a CCM instance that produces tags of `tag_len` bytes, which must be an even
+    /// length from 4 to 16.
+    pub fn new(cipher: C, tag_len: usize) -> Result<Self> {
+        if !TAG_LENGTHS.contains(&tag_len) {
+            return Err(Error::InvalidTagLength(tag_len));
+        }
+
+        Ok(Ccm { cipher, tag_len })
+    }
+
+    /// The length of the tag appended to sealed messages.
+    pub fn tag_len(&self) -> usize {
+        self.tag_len
+    }
+
+    /// Encrypts and authenticates `plaintext`, also authenticating `aad`. Returns the ciphertext
+    /// followed by the tag.
+    pub fn seal(&self, nonce: &[u8], aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+        check_lengths(nonce, plaintext.len())?;
+
+        let mut tag = self.cbc_mac(nonce, aad, plaintext);
+        let mut output = plaintext.to_vec();
+        xor_block(&mut tag, &self.ctr(nonce, &mut output));
+        output.extend_from_slice(&tag[..self.tag_len]);
+
+        Ok(output)
+    }
+
+    /// Verifies and decrypts a message produced by [`Ccm::seal`].
+    ///
+    /// CCM authenticates the plaintext rather than the ciphertext, so the tag can only be
+    /// recomputed after decrypting. The plaintext is returned only if that tag equals the one
+    /// received, compared in constant time.
+    pub fn open(&self, nonce: &[u8], aad: &[u8], sealed: &[u8]) -> Result<Vec<u8>> {
+        if sealed.len() < self.tag_len {
+            return Err(Error::TruncatedInput);
+        }
+
+        let (ciphertext, received_tag) = sealed.split_at(sealed.len() - self.tag_len);
+        check_lengths(nonce, ciphertext.len())?;
+
+        // CBC-MAC runs over the decrypted payload. On a mismatch that payload is unauthenticated,
+        // so it is zeroed before the error is returned rather than left in the freed buffer.
+        let mut output = ciphertext.to_vec();
+        let tag_mask = self.ctr(nonce, &mut output);
+        let mut expected_tag = self.cbc_mac(nonce, aad, &output);
+        xor_block(&mut expected_tag, &tag_mask);
+
+        if !constant_time_eq(&expected_tag[..self.tag_len], received_tag) {
+            output.fill(0);
+            return Err(Error::AuthenticationFailed);
+        }
+
+        Ok(output)
+    }
+
+    /// Computes the CBC-MAC over the formatted blocks B0, the encoded AAD and the padded payload.
+    fn cbc_mac(&self, nonce: &[u8], aad: &[u8], payload: &[u8]) -> [u8; BLOCK_SIZE] {
+        let flags = u8::from(!aad.is_empty()) << 6 | (((self.tag_len - 2) / 2) as u8) << 3;
+        let b0 = format_block(flags, nonce, payload.len() as u64);
+
+        let mut mac = CbcMac::new(&self.cipher, &b0);
+        if !aad.is_empty() {
+            let mut encoded_len = Vec::with_capacity(10);
+            match aad.len() {
+                len if len < 0xff00 => encoded_len.extend_from_slice(&(len as u16).to_be_bytes()),
+                len if (len as u64) < 1 << 32 => {
+                    encoded_len.extend_from_slice(&[0xff, 0xfe]);
+                    encoded_len.extend_from_slice(&(len as u32).to_be_bytes());
+                }
+                len => {
+                    encoded_len.extend_from_slice(&[0xff, 0xff]);
+                    encoded_len.extend_from_slice(&(len as u64).to_be_bytes());
+                }
+            }
+            mac.update(&encoded_len);
+            mac.update(aad);
+            mac.pad();
+        }
+        mac.update(payload);
+        mac.pad();
+
+        mac.state
+    }
+
+    /// XORs the CTR keystream, which starts at counter 1, into `data`. Returns the encryption of
+    /// counter 0, which masks the tag.
+    fn ctr(&self, nonce: &[u8], data: &mut [u8]) -> [u8; BLOCK_SIZE] {
+        let tag_mask = self.cipher.encrypt_block(&format_block(0, nonce, 0));
+
+        // The counter sits in the low `q` bytes and the length checks keep it from carrying into
+        // the nonce, so a plain big-endian increment steps it.
+        let first = format_block(0, nonce, 1);
+        ctr::xor_keystream(
+            &self.cipher,
+            &first,
+            |block| ctr::add_to_counter(block, 1),
+            data,
+        );
+
+        tag_mask
+    }
+}
+
+/// Lays out B0 or a counter block: the flags byte, whose low three bits hold `q - 1`, then the
+/// nonce, then `value` in the remaining `q` bytes. `value` must fit in `q` bytes.
+fn format_block(flags: u8, nonce: &[u8], value: u64) -> [u8; BLOCK_SIZE] {
+    let q = BLOCK_SIZE - 1 - nonce.len();
+    let mut block = [0; BLOCK_SIZE];
+    block[0] = flags | (q - 1) as u8;
+    block[1..=nonce.len()].copy_from_slice(nonce);
+    block[BLOCK_SIZE - q..].copy_from_slice(&value.to_be_bytes()[8 - q..]);
+    block
+}
+
+/// Checks the nonce length and that the payload length fits in the remaining `15 - nonce_len`
+/// bytes of the counter block.
+fn check_lengths(nonce: &[u8], payload_len: usize) -> Result<()> {
+    if !NONCE_LENGTHS.contains(&nonce.len()) {
+        return Err(Error::InvalidIvLength(nonce.len()));
+    }
+
+    let length_bits = 8 * (BLOCK_SIZE - 1 - nonce.len());
+    if length_bits < 64 && (payload_len as u64) >> length_bits != 0 {
+        return Err(Error::InputTooLong(payload_len));
+    }
+
+    Ok(())
+}
+
+/// A CBC-MAC over a byte stream, absorbing a block each time one fills up.
+struct CbcMac<'a, C> {
+    cipher: &'a C,
+    state: [u8; BLOCK_SIZE],
+    buffer: [u8; BLOCK_SIZE],
+    buffered: usize,
+}
+
+impl<'a, C: BlockCipher> CbcMac<'a, C> {
+    fn new(cipher: &'a C, first_block: &[u8; BLOCK_SIZE]) -> Self {
+        CbcMac {
+            cipher,
+            state: cipher.encrypt_block(first_block),
+            buffer: [0; BLOCK_SIZE],
+            buffered: 0,
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.buffer[self.buffered] = byte;
+            self.buffered += 1;
+            if self.buffered == BLOCK_SIZE {
+                self.absorb();
+            }
+        }
+    }
+
+    /// Zero pads a partially filled block and absorbs it.
+    fn pad(&mut self) {
+        if self.buffered != 0 {
+            self.buffer[self.buffered..].fill(0);
+            self.absorb();
+        }
+    }
+
+    fn absorb(&mut self) {
+        xor_block(&mut self.state, &self.buffer);
+        self.state = self.cipher.encrypt_block(&self.state);
+        self.buffered = 0;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cipher::{Aes128, Aes256};
+    use crate::key::Key;
+    use crate::test_util::{check_aead, check_rejects_tampering, hex, hex_array};
+
+    const KEY: &str = "404142434445464748494a4b4c4d4e4f";
+
+    /// Checks an SP 800-38C example, all of which share the key `KEY`.
+    fn check(tag_len: usize, nonce: &str, aad: &[u8], plaintext: &str, sealed: &str) {
+        let ccm = Ccm::new(Aes128::new(&Key::new(hex_array(KEY))), tag_len).unwrap();
+        check_aead(&ccm, &hex(nonce), aad, &hex(plaintext), &hex(sealed));
+    }
+
+    #[test]
+    fn test_sp800_38c_example_1() {
+        check(
+            4,
+            "10111213141516",
+            &hex("0001020304050607"),
+            "20212223",
+            "7162015b 4dac255d",
+        );
+    }
+
+    #[test]
+    fn test_sp800_38c_example_2() {
+        check(
+            6,
+            "1011121314151617",
+            &hex("000102030405060708090a0b0c0d0e0f"),
+            "202122232425262728292a2b2c2d2e2f",
+            "d2a1f0e051ea5f62081a7792073d593d 1fc64fbfaccd",
+        );
+    }
+
+    #[test]
+    fn test_sp800_38c_example_3() {
+        check(
+            8,
+            "101112131415161718191a1b",
+            &hex("000102030405060708090a0b0c0d0e0f10111213"),
+            "202122232425262728292a2b2c2d2e2f3031323334353637",
+            "e3b201a9f5b71a7a9b1ceaeccd97e70b6176aad9a4428aa5 484392fbc1b09951",
+        );
+    }
+
+    #[test]
+    fn test_sp800_38c_example_4() {
+        // The associated data is 65536 bytes, so its length takes the six-byte encoding.
+        let aad: Vec<u8> = (0..65536).map(|i| i as u8).collect();
+        check(
+            14,
+            "101112131415161718191a1b1c",
+            &aad,
+            "202122232425262728292a2b2c2d2e2f 303132333435363738393a3b3c3d3e3f",
+            "69915dad1e84c6376a68c2967e4dab615ae0fd1faec44cc484828529463ccf72
+             b4ac6bec93e8598e7f0dadbcea5b",
+        );
+    }
+
+    #[test]
+    fn test_short_nonce_long_message() {
+        // A 7-byte nonce leaves eight bytes for the counter, and 300 bytes span several batches
+        // of counter blocks. The tag was computed with another implementation.
+        let ccm = Ccm::new(Aes256::new(&Key::new([5; 32])), 16).unwrap();
+        let nonce = [0, 1, 2, 3, 4, 5, 6];
+        let plaintext: Vec<u8> = (0..300).map(|i| (i * 7) as u8).collect();
+
+        let sealed = ccm.seal(&nonce, b"header", &plaintext).unwrap();
+        assert_eq!(sealed[300..], hex("2308e5a7a95e02e0fa11614ad844c6d4"));
+        assert_eq!(ccm.open(&nonce, b"header", &sealed).unwrap(), plaintext);
+    }
+
+    #[test]
+    fn test_invalid_parameters() {
+        let cipher = Aes128::new(&Key::new(hex_array(KEY)));
+        for tag_len in [0, 2, 3, 5, 15, 17] {
+            assert!(matches!(
+                Ccm::new(&cipher, tag_len),
+                Err(Error::InvalidTagLength(n)) if n == tag_len
+            ));
+        }
+
+        let ccm = Ccm::new(&cipher, 8).unwrap();
+        assert_eq!(ccm.tag_len(), 8);
+        for nonce_len in [0, 6, 14, 16] {
+            let nonce = vec![0; nonce_len];
+            assert!(matches!(
+                ccm.seal(&nonce, &[], b"data"),
+                Err(Error::InvalidIvLength(n)) if n == nonce_len
+            ));
+            assert!(matches!(
+                ccm.open(&nonce, &[], &[0; 12]),
+                Err(Error::InvalidIvLength(n)) if n == nonce_len
+            ));
+        }
+
+        // A 13-byte nonce leaves two bytes for the length.
+        let nonce = [0; 13];
+        assert!(ccm.seal(&nonce, &[], &[0; 65535]).is_ok());
+        assert!(matches!(
+            ccm.seal(&nonce, &[], &[0; 65536]),
+            Err(Error::InputTooLong(65536))
+        ));
+    }
+
+    #[test]
+    fn test_open_rejects_tampering() {
+        let ccm = Ccm::new(Aes128::new(&Key::new(hex_array(KEY))), 8).unwrap();
+        let (nonce, aad) = (hex("101112131415161718191a1b"), b"aad");
+        let sealed = check_rejects_tampering(&ccm, &nonce, aad, b"attack at dawn");
+
+        // Empty AAD changes the flags in B0 as well as the MAC input.
+        assert!(ccm.open(&nonce, &[], &sealed).is_err());
+        assert!(matches!(
+            ccm.open(&nonce, aad, &sealed[..7]),
+            Err(Error::TruncatedInput)
+        ));
+    }
+}
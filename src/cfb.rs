@@ -0,0 +1,237 @@
+// This file holds the cipher feedback (CFB) mode from NIST SP 800-38A, which turns the forward
+// block cipher into a self-synchronising stream cipher.
+
+use std::io::{Read, Write};
+
+use crate::cipher::BlockCipher;
+use crate::error::Result;
+use crate::io_util::process_stream;
+
+const BLOCK_SIZE: usize = 16;
+
+/// The number of bits fed back into the shift register after each segment.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Segment {
+    /// CFB-8: one cipher call per byte, so a corrupted byte only garbles itself and the 16 after it.
+    Bits8,
+    /// CFB-128: one cipher call per block.
+    Bits128,
+}
+
+/// A CFB encryptor or decryptor. The ciphertext is exactly as long as the plaintext, and only
+/// the forward cipher is used in both directions.
+pub struct Cfb<C> {
+    cipher: C,
+    segment: Segment,
+    /// The input block for the next cipher call: the IV, then the most recent ciphertext.
+    register: [u8; BLOCK_SIZE],
+    keystream: [u8; BLOCK_SIZE],
+    /// How many bytes of the current CFB-128 segment have been processed.
+    used: usize,
+}
+
+impl<C: BlockCipher> Cfb<C> {
+    pub fn new(cipher: C, iv: &[u8; BLOCK_SIZE], segment: Segment) -> Self {
+        Cfb {
+            cipher,
+            segment,
+            register: *iv,
+            keystream: [0; BLOCK_SIZE],
+            used: BLOCK_SIZE,
+        }
+    }
+
+    /// Encrypts the next `data.len()` bytes in place. The data may be split at any point
+    /// between calls.
+    pub fn encrypt(&mut self, data: &mut [u8]) {
+        for byte in data.iter_mut() {
+            *byte ^= self.next_key_byte();
+            self.feed_back(*byte);
+        }
+    }
+
+    /// Decrypts the next `data.len()` bytes in place. The data may be split at any point
+    /// between calls.
+    pub fn decrypt(&mut self, data: &mut [u8]) {
+        for byte in data.iter_mut() {
+            let ciphertext = *byte;
+            *byte ^= self.next_key_byte();
+            self.feed_back(ciphertext);
+        }
+    }
+
+    fn next_key_byte(&mut self) -> u8 {
+        match self.segment {
+            Segment::Bits8 => self.cipher.encrypt_block(&self.register)[0],
+            Segment::Bits128 => {
+                if self.used == BLOCK_SIZE {
+                    self.keystream = self.cipher.encrypt_block(&self.register);
+                    self.used = 0;
+                }
+                self.keystream[self.used]
+            }
+        }
+    }
+
+    /// Shifts a ciphertext byte into the register. CFB-128 collects a whole block before the
+    /// register is used again, so its bytes are written in place.
+    fn feed_back(&mut self, ciphertext: u8) {
+        match self.segment {
+            Segment::Bits8 => {
+                self.register.copy_within(1.., 0);
+                self.register[BLOCK_SIZE - 1] = ciphertext;
+            }
+            Segment::Bits128 => {
+                self.register[self.used] = ciphertext;
+                self.used += 1;
+            }
+        }
+    }
+}
+
+/// Encrypts the input stream into the output stream. The output is exactly as long as the input.
+pub fn encrypt_stream<R: Read, W: Write, C: BlockCipher>(
+    reader: R,
+    writer: W,
+    cipher: C,
+    iv: &[u8; BLOCK_SIZE],
+    segment: Segment,
+) -> Result<()> {
+    let mut cfb = Cfb::new(cipher, iv, segment);
+    process_stream(reader, writer, |data| cfb.encrypt(data))
+}
+
+/// Decrypts a stream produced by [`encrypt_stream`] with the same IV and segment size.
+pub fn decrypt_stream<R: Read, W: Write, C: BlockCipher>(
+    reader: R,
+    writer: W,
+    cipher: C,
+    iv: &[u8; BLOCK_SIZE],
+    segment: Segment,
+) -> Result<()> {
+    let mut cfb = Cfb::new(cipher, iv, segment);
+    process_stream(reader, writer, |data| cfb.decrypt(data))
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::cipher::{Aes128, Aes256};
+    use crate::key::Key;
+    use crate::test_util::{hex, hex_array, ChunkedReader};
+
+    const SP800_38A_PLAINTEXT: &str = "
+        6bc1bee22e409f96e93d7e117393172a ae2d8a571e03ac9c9eb76fac45af8e51
+        30c81c46a35ce411e5fbc1191a0a52ef f69f2445df4f9b17ad2b417be66c3710";
+
+    const SP800_38A_IV: &str = "000102030405060708090a0b0c0d0e0f";
+
+    fn aes128() -> Aes128 {
+        Aes128::new(&Key::new(hex_array("2b7e151628aed2a6abf7158809cf4f3c")))
+    }
+
+    fn aes256() -> Aes256 {
+        Aes256::new(&Key::new(hex_array(
+            "603deb1015ca71be2b73aef0857d77811f352c073b6108d72d9810a30914dff4",
+        )))
+    }
+
+    fn check<C: BlockCipher>(cipher: C, segment: Segment, plaintext: &[u8], ciphertext: &[u8]) {
+        let iv = hex_array(SP800_38A_IV);
+
+        let mut data = plaintext.to_vec();
+        Cfb::new(&cipher, &iv, segment).encrypt(&mut data);
+        assert_eq!(data, ciphertext);
+
+        // Feed the data through in uneven pieces to exercise partial segments.
+        let mut cfb = Cfb::new(&cipher, &iv, segment);
+        for piece in data.chunks_mut(7) {
+            cfb.decrypt(piece);
+        }
+        assert_eq!(data, plaintext);
+    }
+
+    #[test]
+    fn test_sp800_38a_cfb128_aes128() {
+        let ciphertext = hex(
+            "3b3fd92eb72dad20333449f8e83cfb4a c8a64537a0b3a93fcde3cdad9f1ce58b
+             26751f67a3cbb140b1808cf187a4f4df c04b05357c5d1c0eeac4c66f9ff7f2e6",
+        );
+        check(
+            aes128(),
+            Segment::Bits128,
+            &hex(SP800_38A_PLAINTEXT),
+            &ciphertext,
+        );
+    }
+
+    #[test]
+    fn test_sp800_38a_cfb128_aes256() {
+        let ciphertext = hex(
+            "dc7e84bfda79164b7ecd8486985d3860 39ffed143b28b1c832113c6331e5407b
+             df10132415e54b92a13ed0a8267ae2f9 75a385741ab9cef82031623d55b1e471",
+        );
+        check(
+            aes256(),
+            Segment::Bits128,
+            &hex(SP800_38A_PLAINTEXT),
+            &ciphertext,
+        );
+    }
+
+    // The CFB-8 examples encrypt only the first 18 bytes of the plaintext.
+    #[test]
+    fn test_sp800_38a_cfb8_aes128() {
+        let ciphertext = hex("3b79424c9c0dd436bace9e0ed4586a4f32b9");
+        check(
+            aes128(),
+            Segment::Bits8,
+            &hex(SP800_38A_PLAINTEXT)[..18],
+            &ciphertext,
+        );
+    }
+
+    #[test]
+    fn test_sp800_38a_cfb8_aes256() {
+        let ciphertext = hex("dc1f1a8520a64db55fcc8ac554844e889700");
+        check(
+            aes256(),
+            Segment::Bits8,
+            &hex(SP800_38A_PLAINTEXT)[..18],
+            &ciphertext,
+        );
+    }
+
+    #[test]
+    fn test_streams() {
+        let cipher = Aes256::new(&Key::new([5; 32]));
+        let iv = [0xab; 16];
+
+        for segment in [Segment::Bits8, Segment::Bits128] {
+            for len in [0, 1, 15, 16, 17, 1000, 5000] {
+                let plaintext: Vec<u8> = (0..len).map(|i| (i * 7) as u8).collect();
+
+                let mut expected = plaintext.clone();
+                Cfb::new(&cipher, &iv, segment).encrypt(&mut expected);
+
+                let mut encrypted = Vec::new();
+                let reader = ChunkedReader::random(&plaintext, len as u64).interrupting();
+                encrypt_stream(reader, &mut encrypted, &cipher, &iv, segment).unwrap();
+                assert_eq!(encrypted, expected);
+
+                let mut decrypted = Vec::new();
+                decrypt_stream(
+                    Cursor::new(&encrypted),
+                    &mut decrypted,
+                    &cipher,
+                    &iv,
+                    segment,
+                )
+                .unwrap();
+                assert_eq!(decrypted, plaintext);
+            }
+        }
+    }
+}
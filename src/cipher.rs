@@ -0,0 +1,441 @@
+// This file holds the reusable cipher objects that own an expanded key schedule.
+
+use std::sync::OnceLock;
+
+#[cfg(target_arch = "x86_64")]
+use crate::aesni::AesNi;
+use crate::{
+    bitslice::{self, BitslicedSchedule},
+    decrypt::perform_inverse_rounds,
+    encrypt::perform_rounds,
+    error::Result,
+    key::{Key, KeySchedule},
+    shared::{expand_block_to_state, flatten_state_to_block, inverse_schedule, key_expansion},
+    trace::RoundObserver,
+};
+
+/// A 128-bit block cipher that can encrypt and decrypt single blocks.
+///
+/// Modes of operation are written against this trait so they work with every key size.
+pub trait BlockCipher {
+    /// Encrypts a single 16-byte block.
+    fn encrypt_block(&self, data: &[u8; 16]) -> [u8; 16];
+
+    /// Decrypts a single 16-byte block.
+    fn decrypt_block(&self, data: &[u8; 16]) -> [u8; 16];
+
+    /// Encrypts every block in place.
+    ///
+    /// The default handles one block at a time; backends that can work on several independent
+    /// blocks at once override it, so modes should hand over as many blocks as they have ready.
+    fn encrypt_blocks(&self, blocks: &mut [[u8; 16]]) {
+        for block in blocks {
+            *block = self.encrypt_block(block);
+        }
+    }
+
+    /// Decrypts every block in place. See [`BlockCipher::encrypt_blocks`].
+    fn decrypt_blocks(&self, blocks: &mut [[u8; 16]]) {
+        for block in blocks {
+            *block = self.decrypt_block(block);
+        }
+    }
+}
+
+impl<C: BlockCipher + ?Sized> BlockCipher for &C {
+    fn encrypt_block(&self, data: &[u8; 16]) -> [u8; 16] {
+        (**self).encrypt_block(data)
+    }
+
+    fn decrypt_block(&self, data: &[u8; 16]) -> [u8; 16] {
+        (**self).decrypt_block(data)
+    }
+
+    fn encrypt_blocks(&self, blocks: &mut [[u8; 16]]) {
+        (**self).encrypt_blocks(blocks)
+    }
+
+    fn decrypt_blocks(&self, blocks: &mut [[u8; 16]]) {
+        (**self).decrypt_blocks(blocks)
+    }
+}
+
+macro_rules! aes_cipher {
+    ($name:ident, $key_len:literal, $rounds:literal, $bits:literal) => {
+        #[doc = concat!("An AES-", $bits, " cipher whose round keys are expanded once and reused for every block.")]
+        ///
+        /// Building the cipher up front means bulk operations only pay the key expansion cost
+        /// a single time instead of once per 16-byte block. The forward schedule is expanded at
+        /// construction; the inverse and bitsliced schedules are derived from it the first
+        /// time they are needed, so ciphers that only encrypt, or that run on AES-NI, never
+        /// build them. Every schedule is wiped when the cipher is dropped.
+        ///
+        /// On x86_64 CPUs with AES-NI, blocks are encrypted and decrypted with the hardware
+        /// instructions; elsewhere the software rounds are used. Both give identical output.
+        #[derive(Clone, Debug)]
+        pub struct $name {
+            round_keys: KeySchedule,
+            inverse_round_keys: OnceLock<KeySchedule>,
+            bitsliced: OnceLock<BitslicedSchedule>,
+            bitsliced_inverse: OnceLock<BitslicedSchedule>,
+            #[cfg(target_arch = "x86_64")]
+            aesni: Option<AesNi>,
+        }
+
+        impl $name {
+            /// The key length in bytes.
+            pub const KEY_SIZE: usize = $key_len;
+
+            /// The number of rounds (Nr).
+            pub const ROUNDS: usize = $rounds;
+
+            #[doc = concat!("Expands the key schedule for the given ", $bits, "-bit key.")]
+            pub fn new(key: &Key<$key_len>) -> Self {
+                $name {
+                    #[cfg(target_arch = "x86_64")]
+                    aesni: AesNi::new(key.as_bytes()),
+                    ..$name::software(key)
+                }
+            }
+
+            /// Builds the cipher without the hardware backend, so the software rounds are used
+            /// even where AES-NI is available.
+            pub(crate) fn software(key: &Key<$key_len>) -> Self {
+                $name {
+                    round_keys: key_expansion(key.as_bytes()),
+                    inverse_round_keys: OnceLock::new(),
+                    bitsliced: OnceLock::new(),
+                    bitsliced_inverse: OnceLock::new(),
+                    #[cfg(target_arch = "x86_64")]
+                    aesni: None,
+                }
+            }
+
+            /// Builds the cipher without the hardware backend, for benchmarks that compare the
+            /// software rounds against AES-NI. Only built with the `bench` feature.
+            #[cfg(feature = "bench")]
+            #[doc(hidden)]
+            pub fn software_only(key: &Key<$key_len>) -> Self {
+                $name::software(key)
+            }
+
+            /// Expands the key schedules for a key of unchecked length, such as one read from a
+            #[doc = concat!("file. Fails with [`Error::InvalidKeyLength`](crate::Error::InvalidKeyLength) unless the key is ", $key_len, " bytes long.")]
+            pub fn from_slice(key: &[u8]) -> Result<Self> {
+                Ok($name::new(&Key::from_slice(key)?))
+            }
+
+            /// Encrypts a single 16-byte block.
+            pub fn encrypt_block(&self, data: &[u8; 16]) -> [u8; 16] {
+                #[cfg(target_arch = "x86_64")]
+                if let Some(aesni) = &self.aesni {
+                    return aesni.encrypt_block(data);
+                }
+                self.encrypt_block_traced(data, &mut ())
+            }
+
+            /// Decrypts a single 16-byte block.
+            pub fn decrypt_block(&self, data: &[u8; 16]) -> [u8; 16] {
+                #[cfg(target_arch = "x86_64")]
+                if let Some(aesni) = &self.aesni {
+                    return aesni.decrypt_block(data);
+                }
+                self.decrypt_block_traced(data, &mut ())
+            }
+
+            /// Encrypts every block in place, several at a time: pipelined through AES-NI where
+            /// available, and through the bitsliced backend otherwise.
+            pub fn encrypt_blocks(&self, blocks: &mut [[u8; 16]]) {
+                #[cfg(target_arch = "x86_64")]
+                if let Some(aesni) = &self.aesni {
+                    return aesni.encrypt_blocks(blocks);
+                }
+                bitsliced_blocks(blocks, |group| self.encrypt_8_blocks(group));
+            }
+
+            /// Decrypts every block in place, several at a time.
+            pub fn decrypt_blocks(&self, blocks: &mut [[u8; 16]]) {
+                #[cfg(target_arch = "x86_64")]
+                if let Some(aesni) = &self.aesni {
+                    return aesni.decrypt_blocks(blocks);
+                }
+                bitsliced_blocks(blocks, |group| self.decrypt_8_blocks(group));
+            }
+
+            /// Encrypts eight blocks in place with the bitsliced backend, which is constant time
+            /// and much faster per block than [`Self::encrypt_block`].
+            pub fn encrypt_8_blocks(&self, blocks: &mut [[u8; 16]; bitslice::BLOCKS]) {
+                let schedule = self
+                    .bitsliced
+                    .get_or_init(|| BitslicedSchedule::new(&self.round_keys));
+                bitslice::encrypt(schedule, blocks);
+            }
+
+            /// Decrypts eight blocks in place with the bitsliced backend.
+            pub fn decrypt_8_blocks(&self, blocks: &mut [[u8; 16]; bitslice::BLOCKS]) {
+                let schedule = self
+                    .bitsliced_inverse
+                    .get_or_init(|| BitslicedSchedule::new(self.inverse_round_keys()));
+                bitslice::decrypt(schedule, blocks);
+            }
+
+            /// Encrypts a single block, reporting every intermediate state and round key.
+            ///
+            /// Tracing always runs the software rounds, since the hardware instructions do not
+            /// expose the intermediate states.
+            pub fn encrypt_block_traced<O: RoundObserver>(
+                &self,
+                data: &[u8; 16],
+                observer: &mut O,
+            ) -> [u8; 16] {
+                let mut state = expand_block_to_state(*data);
+                perform_rounds(&mut state, &self.round_keys, observer);
+                flatten_state_to_block(state)
+            }
+
+            /// Decrypts a single block, reporting every intermediate state and round key.
+            pub fn decrypt_block_traced<O: RoundObserver>(
+                &self,
+                data: &[u8; 16],
+                observer: &mut O,
+            ) -> [u8; 16] {
+                let mut state = expand_block_to_state(*data);
+                perform_inverse_rounds(&mut state, self.inverse_round_keys(), observer);
+                flatten_state_to_block(state)
+            }
+
+            fn inverse_round_keys(&self) -> &KeySchedule {
+                self.inverse_round_keys
+                    .get_or_init(|| inverse_schedule(self.round_keys.clone()))
+            }
+        }
+
+        impl BlockCipher for $name {
+            fn encrypt_block(&self, data: &[u8; 16]) -> [u8; 16] {
+                $name::encrypt_block(self, data)
+            }
+
+            fn decrypt_block(&self, data: &[u8; 16]) -> [u8; 16] {
+                $name::decrypt_block(self, data)
+            }
+
+            fn encrypt_blocks(&self, blocks: &mut [[u8; 16]]) {
+                $name::encrypt_blocks(self, blocks)
+            }
+
+            fn decrypt_blocks(&self, blocks: &mut [[u8; 16]]) {
+                $name::decrypt_blocks(self, blocks)
+            }
+        }
+    };
+}
+
+/// Runs `apply` over the blocks in groups of eight. A final partial group is padded out to eight
+/// blocks, which still costs far less than running the leftover blocks through the reference
+/// rounds one at a time.
+fn bitsliced_blocks(
+    blocks: &mut [[u8; 16]],
+    mut apply: impl FnMut(&mut [[u8; 16]; bitslice::BLOCKS]),
+) {
+    let (groups, rest) = blocks.as_chunks_mut::<{ bitslice::BLOCKS }>();
+    for group in groups {
+        apply(group);
+    }
+
+    if !rest.is_empty() {
+        let mut group = [[0; 16]; bitslice::BLOCKS];
+        group[..rest.len()].copy_from_slice(rest);
+        apply(&mut group);
+        rest.copy_from_slice(&group[..rest.len()]);
+    }
+}
+
+aes_cipher!(Aes128, 16, 10, "128");
+aes_cipher!(Aes192, 24, 12, "192");
+aes_cipher!(Aes256, 32, 14, "256");
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::error::Error;
+
+    const PLAINTEXT: [u8; 16] = [
+        0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd, 0xee,
+        0xff,
+    ];
+
+    fn sequential_key<const N: usize>() -> [u8; N] {
+        let mut key = [0; N];
+        for (i, byte) in key.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        key
+    }
+
+    #[test]
+    fn test_fips_197_c1() {
+        let ciphertext: [u8; 16] = [
+            0x69, 0xc4, 0xe0, 0xd8, 0x6a, 0x7b, 0x04, 0x30, 0xd8, 0xcd, 0xb7, 0x80, 0x70, 0xb4,
+            0xc5, 0x5a,
+        ];
+
+        let cipher = Aes128::new(&Key::new(sequential_key()));
+        assert_eq!(cipher.encrypt_block(&PLAINTEXT), ciphertext);
+        assert_eq!(cipher.decrypt_block(&ciphertext), PLAINTEXT);
+    }
+
+    #[test]
+    fn test_fips_197_c2() {
+        let ciphertext: [u8; 16] = [
+            0xdd, 0xa9, 0x7c, 0xa4, 0x86, 0x4c, 0xdf, 0xe0, 0x6e, 0xaf, 0x70, 0xa0, 0xec, 0x0d,
+            0x71, 0x91,
+        ];
+
+        let cipher = Aes192::new(&Key::new(sequential_key()));
+        assert_eq!(cipher.encrypt_block(&PLAINTEXT), ciphertext);
+        assert_eq!(cipher.decrypt_block(&ciphertext), PLAINTEXT);
+    }
+
+    #[test]
+    fn test_fips_197_c3() {
+        let ciphertext: [u8; 16] = [
+            0x8e, 0xa2, 0xb7, 0xca, 0x51, 0x67, 0x45, 0xbf, 0xea, 0xfc, 0x49, 0x90, 0x4b, 0x49,
+            0x60, 0x89,
+        ];
+
+        let cipher = Aes256::new(&Key::new(sequential_key()));
+        assert_eq!(cipher.encrypt_block(&PLAINTEXT), ciphertext);
+        assert_eq!(cipher.decrypt_block(&ciphertext), PLAINTEXT);
+    }
+
+    #[test]
+    fn test_reuse_across_blocks() {
+        let key = [0x42; 32];
+        let cipher = Aes256::new(&Key::new(key));
+
+        for i in 0..=255u8 {
+            let block = [i; 16];
+            let encrypted = cipher.encrypt_block(&block);
+            assert_ne!(encrypted, block);
+            assert_eq!(cipher.decrypt_block(&encrypted), block);
+        }
+    }
+
+    #[test]
+    fn test_block_cipher_trait() {
+        fn round_trip<C: BlockCipher>(cipher: &C) {
+            let encrypted = cipher.encrypt_block(&PLAINTEXT);
+            assert_eq!(cipher.decrypt_block(&encrypted), PLAINTEXT);
+        }
+
+        round_trip(&Aes128::new(&Key::new([7; 16])));
+        round_trip(&Aes192::new(&Key::new([7; 24])));
+        round_trip(&Aes256::new(&Key::new([7; 32])));
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_hardware_matches_software() {
+        use rand::{rngs::StdRng, Rng, SeedableRng};
+
+        macro_rules! compare {
+            ($rng:ident, $cipher:ident, $key_len:literal) => {
+                let key = Key::new($rng.gen::<[u8; $key_len]>());
+                let detected = $cipher::new(&key);
+                let software = $cipher::software(&key);
+                assert!(software.aesni.is_none());
+                assert_eq!(detected.aesni.is_some(), is_x86_feature_detected!("aes"));
+
+                for _ in 0..50 {
+                    let block = $rng.gen::<[u8; 16]>();
+                    let encrypted = detected.encrypt_block(&block);
+                    assert_eq!(encrypted, software.encrypt_block(&block));
+                    assert_eq!(detected.decrypt_block(&encrypted), block);
+                    assert_eq!(software.decrypt_block(&encrypted), block);
+                }
+            };
+        }
+
+        let mut rng = StdRng::seed_from_u64(18);
+        for _ in 0..10 {
+            compare!(rng, Aes128, 16);
+            compare!(rng, Aes192, 24);
+            compare!(rng, Aes256, 32);
+        }
+    }
+
+    #[test]
+    fn test_blocks_match_single_blocks() {
+        let key = Key::new([0x24; 32]);
+
+        // The detected cipher may pipeline through AES-NI; the software one always bitslices.
+        for cipher in [Aes256::new(&key), Aes256::software(&key)] {
+            for len in [0, 1, 7, 8, 9, 19] {
+                let plaintext: Vec<[u8; 16]> = (0..len).map(|i| [i as u8; 16]).collect();
+                let mut blocks = plaintext.clone();
+
+                cipher.encrypt_blocks(&mut blocks);
+                for (block, original) in blocks.iter().zip(&plaintext) {
+                    assert_eq!(*block, cipher.encrypt_block(original));
+                }
+
+                cipher.decrypt_blocks(&mut blocks);
+                assert_eq!(blocks, plaintext);
+            }
+        }
+    }
+
+    #[test]
+    fn test_inverse_schedules_built_on_demand() {
+        let cipher = Aes256::software(&Key::new([0x24; 32]));
+        let mut blocks = [[1; 16]; 8];
+
+        cipher.encrypt_blocks(&mut blocks);
+        assert!(cipher.bitsliced.get().is_some());
+        assert!(cipher.inverse_round_keys.get().is_none());
+        assert!(cipher.bitsliced_inverse.get().is_none());
+
+        cipher.decrypt_blocks(&mut blocks);
+        assert_eq!(blocks, [[1; 16]; 8]);
+        assert!(cipher.inverse_round_keys.get().is_some());
+        assert!(cipher.bitsliced_inverse.get().is_some());
+    }
+
+    #[test]
+    fn test_lazy_inverse_schedule_matches_expansion() {
+        let key = Key::new([0x24; 24]);
+        let cipher = Aes192::software(&key);
+        let expected = crate::shared::inverse_key_expansion(key.as_bytes());
+        assert_eq!(cipher.inverse_round_keys().words, expected.words);
+
+        // A clone taken before the schedule is built builds its own.
+        let cipher = Aes192::software(&key);
+        let copy = cipher.clone();
+        let block = cipher.encrypt_block(&[3; 16]);
+        assert_eq!(cipher.decrypt_block(&block), [3; 16]);
+        assert!(copy.inverse_round_keys.get().is_none());
+        assert_eq!(copy.decrypt_block(&block), [3; 16]);
+    }
+
+    #[test]
+    fn test_from_slice_checks_key_length() {
+        let cipher = Aes192::from_slice(&[7; 24]).unwrap();
+        assert_eq!(
+            cipher.encrypt_block(&PLAINTEXT),
+            Aes192::new(&Key::new([7; 24])).encrypt_block(&PLAINTEXT)
+        );
+
+        assert!(matches!(
+            Aes128::from_slice(&[7; 32]),
+            Err(Error::InvalidKeyLength(32))
+        ));
+        assert!(matches!(
+            Aes256::from_slice(&[7; 16]),
+            Err(Error::InvalidKeyLength(16))
+        ));
+        assert!(matches!(
+            Aes256::from_slice(&[]),
+            Err(Error::InvalidKeyLength(0))
+        ));
+    }
+}
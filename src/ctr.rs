@@ -0,0 +1,235 @@
+// This file holds the counter (CTR) mode, which turns the block cipher into a seekable stream cipher.
+
+use std::io::{Read, Write};
+
+use crate::cipher::BlockCipher;
+use crate::error::Result;
+use crate::io_util::process_stream;
+
+const BLOCK_SIZE: usize = 16;
+
+/// The number of counter blocks encrypted together each time the keystream runs out.
+const KEYSTREAM_BLOCKS: usize = 8;
+
+const KEYSTREAM_LEN: usize = KEYSTREAM_BLOCKS * BLOCK_SIZE;
+
+/// A CTR keystream generator. Encryption and decryption are the same operation.
+///
+/// The counter block is treated as a single 128-bit big-endian integer that is incremented once
+/// per block, as in NIST SP 800-38A.
+pub struct Ctr<C> {
+    cipher: C,
+    initial_counter: [u8; BLOCK_SIZE],
+    counter: [u8; BLOCK_SIZE],
+    keystream: [[u8; BLOCK_SIZE]; KEYSTREAM_BLOCKS],
+    used: usize,
+    offset: u64,
+}
+
+impl<C: BlockCipher> Ctr<C> {
+    pub fn new(cipher: C, initial_counter: &[u8; BLOCK_SIZE]) -> Self {
+        Ctr {
+            cipher,
+            initial_counter: *initial_counter,
+            counter: *initial_counter,
+            keystream: [[0; BLOCK_SIZE]; KEYSTREAM_BLOCKS],
+            used: KEYSTREAM_LEN,
+            offset: 0,
+        }
+    }
+
+    /// XORs the keystream into `data`, advancing the position by `data.len()` bytes.
+    pub fn apply_keystream(&mut self, data: &mut [u8]) {
+        for byte in data.iter_mut() {
+            if self.used == KEYSTREAM_LEN {
+                self.refill();
+            }
+            *byte ^= self.keystream.as_flattened()[self.used];
+            self.used += 1;
+        }
+        self.offset += data.len() as u64;
+    }
+
+    /// Moves the keystream to an absolute byte offset from the initial counter block.
+    pub fn seek(&mut self, offset: u64) {
+        self.counter = add_to_counter(&self.initial_counter, (offset / BLOCK_SIZE as u64) as u128);
+        self.used = KEYSTREAM_LEN;
+        self.offset = offset;
+
+        let within_block = (offset % BLOCK_SIZE as u64) as usize;
+        if within_block != 0 {
+            self.refill();
+            self.used = within_block;
+        }
+    }
+
+    /// The current byte offset into the keystream.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Encrypts the next batch of counter blocks in one call, so the cipher can pipeline them.
+    fn refill(&mut self) {
+        for block in self.keystream.iter_mut() {
+            *block = self.counter;
+            self.counter = add_to_counter(&self.counter, 1);
+        }
+        self.cipher.encrypt_blocks(&mut self.keystream);
+        self.used = 0;
+    }
+}
+
+/// Encrypts or decrypts the input stream into the output stream. The output is exactly as long as
+/// the input.
+pub fn apply_stream<R: Read, W: Write, C: BlockCipher>(
+    reader: R,
+    writer: W,
+    cipher: C,
+    initial_counter: &[u8; BLOCK_SIZE],
+) -> Result<()> {
+    let mut ctr = Ctr::new(cipher, initial_counter);
+    process_stream(reader, writer, |data| ctr.apply_keystream(data))
+}
+
+/// XORs a counter-mode keystream into `data` for the authenticated modes, which each step their
+/// counter blocks differently. Starting from `counter`, each block is followed by
+/// `increment(block)`, and the blocks are encrypted in batches so the cipher can pipeline them.
+pub(crate) fn xor_keystream<C: BlockCipher>(
+    cipher: &C,
+    counter: &[u8; BLOCK_SIZE],
+    increment: impl Fn(&[u8; BLOCK_SIZE]) -> [u8; BLOCK_SIZE],
+    data: &mut [u8],
+) {
+    let mut counter = *counter;
+    for chunk in data.chunks_mut(KEYSTREAM_LEN) {
+        let mut keystream = [[0; BLOCK_SIZE]; KEYSTREAM_BLOCKS];
+        let blocks = &mut keystream[..chunk.len().div_ceil(BLOCK_SIZE)];
+        for block in blocks.iter_mut() {
+            *block = counter;
+            counter = increment(&counter);
+        }
+        cipher.encrypt_blocks(blocks);
+
+        for (byte, k) in chunk.iter_mut().zip(blocks.as_flattened()) {
+            *byte ^= k;
+        }
+    }
+}
+
+/// Adds `amount` to a big-endian counter block, wrapping at 2^128.
+pub(crate) fn add_to_counter(counter: &[u8; BLOCK_SIZE], amount: u128) -> [u8; BLOCK_SIZE] {
+    u128::from_be_bytes(*counter)
+        .wrapping_add(amount)
+        .to_be_bytes()
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::cipher::{Aes128, Aes256};
+    use crate::key::Key;
+    use crate::test_util::{hex, hex_array, ChunkedReader};
+
+    const SP800_38A_PLAINTEXT: &str = "
+        6bc1bee22e409f96e93d7e117393172a ae2d8a571e03ac9c9eb76fac45af8e51
+        30c81c46a35ce411e5fbc1191a0a52ef f69f2445df4f9b17ad2b417be66c3710";
+
+    #[test]
+    fn test_sp800_38a_ctr_aes128() {
+        let cipher = Aes128::new(&Key::new(hex_array("2b7e151628aed2a6abf7158809cf4f3c")));
+        let counter = hex_array("f0f1f2f3f4f5f6f7f8f9fafbfcfdfeff");
+        let ciphertext = hex(
+            "874d6191b620e3261bef6864990db6ce 9806f66b7970fdff8617187bb9fffdff
+             5ae4df3edbd5d35e5b4f09020db03eab 1e031dda2fbe03d1792170a0f3009cee",
+        );
+
+        let mut data = hex(SP800_38A_PLAINTEXT);
+        Ctr::new(&cipher, &counter).apply_keystream(&mut data);
+        assert_eq!(data, ciphertext);
+
+        Ctr::new(&cipher, &counter).apply_keystream(&mut data);
+        assert_eq!(data, hex(SP800_38A_PLAINTEXT));
+    }
+
+    #[test]
+    fn test_sp800_38a_ctr_aes256() {
+        let cipher = Aes256::new(&Key::new(hex_array(
+            "603deb1015ca71be2b73aef0857d77811f352c073b6108d72d9810a30914dff4",
+        )));
+        let counter = hex_array("f0f1f2f3f4f5f6f7f8f9fafbfcfdfeff");
+        let ciphertext = hex(
+            "601ec313775789a5b7a7f504bbf3d228 f443e3ca4d62b59aca84e990cacaf5c5
+             2b0930daa23de94ce87017ba2d84988d dfc9c58db67aada613c2dd08457941a6",
+        );
+
+        // Feed the data through in uneven pieces to exercise partial keystream blocks.
+        let mut data = hex(SP800_38A_PLAINTEXT);
+        let mut ctr = Ctr::new(&cipher, &counter);
+        for piece in data.chunks_mut(7) {
+            ctr.apply_keystream(piece);
+        }
+        assert_eq!(data, ciphertext);
+        assert_eq!(ctr.offset(), 64);
+    }
+
+    #[test]
+    fn test_seek() {
+        let cipher = Aes256::new(&Key::new([5; 32]));
+        let counter = [0xab; 16];
+
+        let mut keystream = vec![0u8; 100];
+        Ctr::new(&cipher, &counter).apply_keystream(&mut keystream);
+
+        for offset in 0..keystream.len() {
+            let mut ctr = Ctr::new(&cipher, &counter);
+            ctr.seek(offset as u64);
+            let mut tail = vec![0u8; keystream.len() - offset];
+            ctr.apply_keystream(&mut tail);
+            assert_eq!(tail, keystream[offset..], "offset {}", offset);
+        }
+
+        // Seeking backwards after use restarts from the requested position.
+        let mut ctr = Ctr::new(&cipher, &counter);
+        ctr.apply_keystream(&mut [0u8; 50]);
+        ctr.seek(3);
+        let mut tail = [0u8; 10];
+        ctr.apply_keystream(&mut tail);
+        assert_eq!(tail, keystream[3..13]);
+    }
+
+    #[test]
+    fn test_counter_wraps() {
+        assert_eq!(add_to_counter(&[0xff; 16], 1), [0; 16]);
+
+        let mut expected = [0; 16];
+        expected[14] = 1;
+        let mut counter = [0; 16];
+        counter[15] = 0xff;
+        assert_eq!(add_to_counter(&counter, 1), expected);
+    }
+
+    #[test]
+    fn test_apply_stream() {
+        let cipher = Aes256::new(&Key::new([5; 32]));
+        let counter = [0; 16];
+
+        for len in [0, 1, 15, 16, 17, 1000, 5000] {
+            let plaintext: Vec<u8> = (0..len).map(|i| (i * 7) as u8).collect();
+
+            let mut encrypted = Vec::new();
+            apply_stream(Cursor::new(&plaintext), &mut encrypted, &cipher, &counter).unwrap();
+            assert_eq!(encrypted.len(), plaintext.len());
+
+            let mut decrypted = Vec::new();
+            apply_stream(Cursor::new(&encrypted), &mut decrypted, &cipher, &counter).unwrap();
+            assert_eq!(decrypted, plaintext);
+
+            let mut chunked = Vec::new();
+            let reader = ChunkedReader::random(&plaintext, len as u64).interrupting();
+            apply_stream(reader, &mut chunked, &cipher, &counter).unwrap();
+            assert_eq!(chunked, encrypted);
+        }
+    }
+}
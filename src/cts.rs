@@ -0,0 +1,336 @@
+// This file holds the length-preserving ciphertext stealing modes from the NIST SP 800-38A addendum.
+
+use crate::cipher::BlockCipher;
+use crate::error::{Error, Result};
+use crate::shared::xor_block;
+
+const BLOCK_SIZE: usize = 16;
+
+/// How the final two ciphertext blocks are ordered, as defined in the SP 800-38A addendum.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Variant {
+    /// The partial penultimate block always comes before the final full block.
+    Cs1,
+    /// Like `Cs1` when the data is block aligned, otherwise like `Cs3`. Aligned data produces
+    /// exactly the same output as the underlying mode without stealing.
+    Cs2,
+    /// The final two blocks are always swapped, as in Kerberos (RFC 3962).
+    Cs3,
+}
+
+impl Variant {
+    fn swaps_final_blocks(self, final_len: usize) -> bool {
+        match self {
+            Variant::Cs1 => false,
+            Variant::Cs2 => final_len != BLOCK_SIZE,
+            Variant::Cs3 => true,
+        }
+    }
+}
+
+/// Encrypts `data` in place with CBC and ciphertext stealing. `data` must be at least one block.
+pub fn cbc_encrypt<C: BlockCipher>(
+    cipher: C,
+    iv: &[u8; BLOCK_SIZE],
+    data: &mut [u8],
+    variant: Variant,
+) -> Result<()> {
+    encrypt_with(cipher, Some(iv), data, variant)
+}
+
+/// Decrypts data produced by [`cbc_encrypt`] in place.
+pub fn cbc_decrypt<C: BlockCipher>(
+    cipher: C,
+    iv: &[u8; BLOCK_SIZE],
+    data: &mut [u8],
+    variant: Variant,
+) -> Result<()> {
+    decrypt_with(cipher, Some(iv), data, variant)
+}
+
+/// Encrypts `data` in place with ECB and ciphertext stealing. `data` must be at least one block.
+pub fn ecb_encrypt<C: BlockCipher>(cipher: C, data: &mut [u8], variant: Variant) -> Result<()> {
+    encrypt_with(cipher, None, data, variant)
+}
+
+/// Decrypts data produced by [`ecb_encrypt`] in place.
+pub fn ecb_decrypt<C: BlockCipher>(cipher: C, data: &mut [u8], variant: Variant) -> Result<()> {
+    decrypt_with(cipher, None, data, variant)
+}
+
+/// Splits `data` into the length of its final (possibly partial) block and the offset of the
+/// penultimate block, which is where stealing starts. Returns `None` if `data` is shorter than a
+/// block.
+fn split_final_blocks(data: &[u8]) -> Option<(usize, usize)> {
+    if data.len() < BLOCK_SIZE {
+        return None;
+    }
+
+    let final_len = match data.len() % BLOCK_SIZE {
+        0 => BLOCK_SIZE,
+        partial => partial,
+    };
+
+    // A single full block has no penultimate block to steal from; it is handled as the
+    // "penultimate" block with an empty final block.
+    if data.len() == BLOCK_SIZE {
+        return Some((0, 0));
+    }
+
+    Some((final_len, data.len() - final_len - BLOCK_SIZE))
+}
+
+/// `iv` selects CBC chaining when present and ECB otherwise.
+fn encrypt_with<C: BlockCipher>(
+    cipher: C,
+    iv: Option<&[u8; BLOCK_SIZE]>,
+    data: &mut [u8],
+    variant: Variant,
+) -> Result<()> {
+    let (final_len, steal_at) = split_final_blocks(data).ok_or(Error::InputTooShort(data.len()))?;
+    let mut chain = iv.copied().unwrap_or([0; BLOCK_SIZE]);
+
+    // Every block before the final one is encrypted normally.
+    for chunk in data[..steal_at + BLOCK_SIZE].chunks_exact_mut(BLOCK_SIZE) {
+        let block: &mut [u8; BLOCK_SIZE] = chunk.try_into().unwrap();
+        xor_block(block, &chain);
+        *block = cipher.encrypt_block(block);
+        if iv.is_some() {
+            chain = *block;
+        }
+    }
+
+    if final_len == 0 {
+        return Ok(());
+    }
+
+    let penultimate: [u8; BLOCK_SIZE] = data[steal_at..steal_at + BLOCK_SIZE].try_into().unwrap();
+
+    // The final block is zero padded for CBC, or padded with the stolen tail of the penultimate
+    // ciphertext block for ECB. Either way that tail can be recovered during decryption.
+    let mut last = if iv.is_some() {
+        [0; BLOCK_SIZE]
+    } else {
+        penultimate
+    };
+    last[..final_len].copy_from_slice(&data[steal_at + BLOCK_SIZE..]);
+    if iv.is_some() {
+        xor_block(&mut last, &penultimate);
+    }
+    let last = cipher.encrypt_block(&last);
+
+    let region = &mut data[steal_at..];
+    if variant.swaps_final_blocks(final_len) {
+        region[..BLOCK_SIZE].copy_from_slice(&last);
+        region[BLOCK_SIZE..].copy_from_slice(&penultimate[..final_len]);
+    } else {
+        region[..final_len].copy_from_slice(&penultimate[..final_len]);
+        region[final_len..].copy_from_slice(&last);
+    }
+
+    Ok(())
+}
+
+fn decrypt_with<C: BlockCipher>(
+    cipher: C,
+    iv: Option<&[u8; BLOCK_SIZE]>,
+    data: &mut [u8],
+    variant: Variant,
+) -> Result<()> {
+    let (final_len, steal_at) = split_final_blocks(data).ok_or(Error::TruncatedInput)?;
+    let mut chain = iv.copied().unwrap_or([0; BLOCK_SIZE]);
+
+    for chunk in data[..steal_at].chunks_exact_mut(BLOCK_SIZE) {
+        let block: &mut [u8; BLOCK_SIZE] = chunk.try_into().unwrap();
+        let ciphertext = *block;
+        *block = cipher.decrypt_block(block);
+        xor_block(block, &chain);
+        if iv.is_some() {
+            chain = ciphertext;
+        }
+    }
+
+    if final_len == 0 {
+        let block: &mut [u8; BLOCK_SIZE] = data.try_into().unwrap();
+        *block = cipher.decrypt_block(block);
+        xor_block(block, &chain);
+        return Ok(());
+    }
+
+    let region = &mut data[steal_at..];
+    let (stolen_from, last): (&[u8], [u8; BLOCK_SIZE]) = if variant.swaps_final_blocks(final_len) {
+        (
+            &region[BLOCK_SIZE..],
+            region[..BLOCK_SIZE].try_into().unwrap(),
+        )
+    } else {
+        (
+            &region[..final_len],
+            region[final_len..].try_into().unwrap(),
+        )
+    };
+
+    // Decrypting the final block yields the stolen tail of the penultimate ciphertext block.
+    let decrypted_last = cipher.decrypt_block(&last);
+    let mut penultimate = decrypted_last;
+    penultimate[..final_len].copy_from_slice(stolen_from);
+
+    let mut final_plaintext = [0; BLOCK_SIZE];
+    final_plaintext[..final_len].copy_from_slice(&decrypted_last[..final_len]);
+    if iv.is_some() {
+        xor_block(&mut final_plaintext, &penultimate);
+    }
+
+    let mut penultimate_plaintext = cipher.decrypt_block(&penultimate);
+    xor_block(&mut penultimate_plaintext, &chain);
+
+    region[..BLOCK_SIZE].copy_from_slice(&penultimate_plaintext);
+    region[BLOCK_SIZE..].copy_from_slice(&final_plaintext[..final_len]);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cipher::{Aes128, Aes256};
+    use crate::key::Key;
+    use crate::test_util::{hex, hex_array};
+
+    const RFC_3962_PLAINTEXT: &[u8] =
+        b"I would like the General Gau's Chicken, please, and wonton soup.";
+
+    #[test]
+    fn test_rfc_3962_cbc_cs3() {
+        let cipher = Aes128::new(&Key::new(hex_array("636869636b656e207465726979616b69")));
+        let iv = [0; 16];
+        let vectors = [
+            (17, "c6353568f2bf8cb4d8a580362da7ff7f 97"),
+            (
+                31,
+                "fc00783e0efdb2c1d445d4c8eff7ed22 97687268d6ecccc0c07b25e25ecfe5",
+            ),
+            (
+                32,
+                "39312523a78662d5be7fcbcc98ebf5a8 97687268d6ecccc0c07b25e25ecfe584",
+            ),
+            (
+                47,
+                "97687268d6ecccc0c07b25e25ecfe584 b3fffd940c16a18c1b5549d2f838029e
+                 39312523a78662d5be7fcbcc98ebf5",
+            ),
+            (
+                48,
+                "97687268d6ecccc0c07b25e25ecfe584 9dad8bbb96c4cdc03bc103e1a194bbd8
+                 39312523a78662d5be7fcbcc98ebf5a8",
+            ),
+            (
+                64,
+                "97687268d6ecccc0c07b25e25ecfe584 39312523a78662d5be7fcbcc98ebf5a8
+                 4807efe836ee89a526730dbc2f7bc840 9dad8bbb96c4cdc03bc103e1a194bbd8",
+            ),
+        ];
+
+        for (len, expected) in vectors {
+            let mut data = RFC_3962_PLAINTEXT[..len].to_vec();
+            cbc_encrypt(&cipher, &iv, &mut data, Variant::Cs3).unwrap();
+            assert_eq!(data, hex(expected), "length {}", len);
+
+            cbc_decrypt(&cipher, &iv, &mut data, Variant::Cs3).unwrap();
+            assert_eq!(data, RFC_3962_PLAINTEXT[..len], "length {}", len);
+        }
+    }
+
+    #[test]
+    fn test_variants_only_differ_in_block_order() {
+        let cipher = Aes128::new(&Key::new(hex_array("636869636b656e207465726979616b69")));
+        let iv = [0; 16];
+
+        for len in 17..=RFC_3962_PLAINTEXT.len() {
+            let encrypt = |variant| {
+                let mut data = RFC_3962_PLAINTEXT[..len].to_vec();
+                cbc_encrypt(&cipher, &iv, &mut data, variant).unwrap();
+                data
+            };
+            let (cs1, cs2, cs3) = (
+                encrypt(Variant::Cs1),
+                encrypt(Variant::Cs2),
+                encrypt(Variant::Cs3),
+            );
+
+            let final_len = if len % 16 == 0 { 16 } else { len % 16 };
+            let steal_at = len - final_len - 16;
+            let mut swapped = cs1[..steal_at].to_vec();
+            swapped.extend_from_slice(&cs1[steal_at + final_len..]);
+            swapped.extend_from_slice(&cs1[steal_at..steal_at + final_len]);
+
+            assert_eq!(cs3, swapped, "length {}", len);
+            assert_eq!(
+                &cs2,
+                if final_len == 16 { &cs1 } else { &cs3 },
+                "length {}",
+                len
+            );
+        }
+    }
+
+    #[test]
+    fn test_cs1_matches_cbc_when_aligned() {
+        let cipher = Aes256::new(&Key::new([1; 32]));
+        let iv = [2; 16];
+        let plaintext: Vec<u8> = (0..64).collect();
+
+        let mut data = plaintext.clone();
+        cbc_encrypt(&cipher, &iv, &mut data, Variant::Cs1).unwrap();
+        let padded = crate::cbc::encrypt(&cipher, &iv, &plaintext);
+        assert_eq!(data, padded[..64]);
+    }
+
+    #[test]
+    fn test_round_trips() {
+        let cipher = Aes256::new(&Key::new([1; 32]));
+        let iv = [2; 16];
+
+        for variant in [Variant::Cs1, Variant::Cs2, Variant::Cs3] {
+            for len in 16..100 {
+                let plaintext: Vec<u8> = (0..len).map(|i| (i * 3) as u8).collect();
+
+                let mut data = plaintext.clone();
+                cbc_encrypt(&cipher, &iv, &mut data, variant).unwrap();
+                assert_ne!(data, plaintext);
+                cbc_decrypt(&cipher, &iv, &mut data, variant).unwrap();
+                assert_eq!(data, plaintext, "CBC {:?} length {}", variant, len);
+
+                let mut data = plaintext.clone();
+                ecb_encrypt(&cipher, &mut data, variant).unwrap();
+                assert_ne!(data, plaintext);
+                ecb_decrypt(&cipher, &mut data, variant).unwrap();
+                assert_eq!(data, plaintext, "ECB {:?} length {}", variant, len);
+            }
+        }
+    }
+
+    #[test]
+    fn test_ecb_single_block() {
+        let cipher = Aes256::new(&Key::new([1; 32]));
+        let mut data = [0x5a; 16];
+        ecb_encrypt(&cipher, &mut data, Variant::Cs2).unwrap();
+        assert_eq!(data, cipher.encrypt_block(&[0x5a; 16]));
+    }
+
+    #[test]
+    fn test_rejects_short_input() {
+        let cipher = Aes256::new(&Key::new([1; 32]));
+        for len in 0..16 {
+            let mut data = vec![0; len];
+            assert!(matches!(
+                cbc_encrypt(&cipher, &[0; 16], &mut data, Variant::Cs3),
+                Err(Error::InputTooShort(n)) if n == len
+            ));
+            assert!(matches!(
+                ecb_decrypt(&cipher, &mut data, Variant::Cs3),
+                Err(Error::TruncatedInput)
+            ));
+        }
+    }
+}
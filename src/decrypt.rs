@@ -1,136 +1,114 @@
 // This file holds the code to decrypt using our implementation of the AES algorithm.
 
-use std::{
-    io::{self, Read, Write},
-    usize,
-};
+use std::io::{Read, Write};
 
+use crate::cipher::Aes256;
+use crate::cts::{self, Variant};
+use crate::error::Result;
+use crate::io_util::read_full;
+use crate::key::Key256;
 use crate::shared::{
-    add_round_key, expand_block_to_state, flatten_state_to_block, inverse_key_expansion,
-    inverse_mix_columns, inverse_shift_rows, inverse_sub_bytes,
+    add_round_key, flatten_state_to_block, inverse_mix_columns, inverse_shift_rows,
+    inverse_sub_bytes, round_key_to_block,
 };
-
-pub fn decrypt_block(data: &[u8; 16], key: &[u8; 32]) -> [u8; 16] {
-    let round_key = inverse_key_expansion(*key);
-    let mut output: [u8; 16] = [0; 16];
-    output.copy_from_slice(data);
-    let mut state = expand_block_to_state(output);
-
-    // Perform the decryption rounds
-    //perform_inverse_rounds(&mut state, &round_key);
-    perform_inverse_rounds(&mut state, &round_key);
-    output = flatten_state_to_block(state);
-
-    return output;
-}
+use crate::trace::{RoundObserver, Step};
 
 const BLOCK_SIZE: usize = 16;
-pub fn decrypt_stream<R: Read, W: Write>(
-    mut reader: R,
-    mut writer: W,
-    key: &[u8; 32],
-) -> io::Result<()> {
-    let mut buffer = [0u8; BLOCK_SIZE];
-    let mut decrypted_blocks = Vec::new();
+
+/// Ciphertext within this many bytes of the end may be part of the stolen final blocks, so it is
+/// held back until the end of the stream is reached.
+const HELD_BACK: usize = 2 * BLOCK_SIZE;
+
+/// Decrypts a stream produced by `encrypt_stream`, undoing the ciphertext stealing on the final
+/// blocks.
+///
+/// Blocks are written out as soon as they are known not to be among the final two, so memory use
+/// stays constant regardless of the length of the stream. A ciphertext shorter than one block
+/// fails with [`Error::TruncatedInput`](crate::Error::TruncatedInput).
+pub fn decrypt_stream<R: Read, W: Write>(mut reader: R, mut writer: W, key: &Key256) -> Result<()> {
+    let cipher = Aes256::new(key);
+    let mut buffer = [0u8; 64 * BLOCK_SIZE];
+    let mut ciphertext = Vec::with_capacity(buffer.len() + HELD_BACK + BLOCK_SIZE);
 
     loop {
-        let read_size = reader.read(&mut buffer)?;
+        let read_size = read_full(&mut reader, &mut buffer)?;
         if read_size == 0 {
             break;
         }
 
-        let decrypted = decrypt_block(&buffer, key);
-        decrypted_blocks.push((decrypted.to_vec(), read_size));
-    }
+        ciphertext.extend_from_slice(&buffer[..read_size]);
 
-    // Write all blocks except the last one fully
-    for (block, size) in decrypted_blocks.iter().take(decrypted_blocks.len() - 1) {
-        writer.write_all(block)?;
+        // Decrypt every whole block that still leaves at least HELD_BACK bytes behind it.
+        let ready = ciphertext.len().saturating_sub(HELD_BACK) / BLOCK_SIZE * BLOCK_SIZE;
+        cipher.decrypt_blocks(ciphertext[..ready].as_chunks_mut().0);
+        writer.write_all(&ciphertext[..ready])?;
+        ciphertext.drain(..ready);
     }
 
-    // Write the last block according to its original size
-    if let Some((last_block, size)) = decrypted_blocks.last() {
-        writer.write_all(&last_block[..*size])?;
+    if ciphertext.is_empty() {
+        return Ok(());
     }
 
+    cts::ecb_decrypt(&cipher, &mut ciphertext, Variant::Cs2)?;
+    writer.write_all(&ciphertext)?;
+
     Ok(())
 }
 
-fn perform_inverse_rounds(state: &mut [[u8; 4]; 4], round_keys: &[u32; 60]) {
-    println!("round[ 0].iinput\t{:x?}", state);
-    println!(
-        "round[ 0].ik_sch\t{:x?}",
+/// Runs the equivalent inverse cipher over `state`; the number of rounds is implied by the length
+/// of `round_keys`.
+///
+/// Every intermediate value is reported to `observer` using the FIPS-197 Appendix C names.
+pub(crate) fn perform_inverse_rounds<O: RoundObserver>(
+    state: &mut [[u8; 4]; 4],
+    round_keys: &[u32],
+    observer: &mut O,
+) {
+    let nr = round_keys.len() / 4 - 1;
+    let round_key = |i: usize| {
         [
-            round_keys[4 * 14],
-            round_keys[4 * 14 + 1],
-            round_keys[4 * 14 + 2],
-            round_keys[4 * 14 + 3],
+            round_keys[4 * i],
+            round_keys[4 * i + 1],
+            round_keys[4 * i + 2],
+            round_keys[4 * i + 3],
         ]
-    );
+    };
+
+    observer.observe(0, Step::IInput, &flatten_state_to_block(*state));
+    observer.observe(0, Step::IKSch, &round_key_to_block(round_key(nr)));
+
     // Start with the final round key (after all rounds)
-    *state = add_round_key(
-        *state,
-        [
-            round_keys[4 * 14],
-            round_keys[4 * 14 + 1],
-            round_keys[4 * 14 + 2],
-            round_keys[4 * 14 + 3],
-        ],
-    );
+    *state = add_round_key(*state, round_key(nr));
 
     // Do the rest of the rounds in reverse order
-    for i in (1..14).rev() {
-        println!("round[ {}].istart\t{:x?}", 14 - i, state);
+    for i in (1..nr).rev() {
+        observer.observe(nr - i, Step::IStart, &flatten_state_to_block(*state));
 
         inverse_sub_bytes(state);
-        println!("round[ {}].is_box\t{:x?}", 14 - i, state);
+        observer.observe(nr - i, Step::ISBox, &flatten_state_to_block(*state));
 
         inverse_shift_rows(state);
-        println!("round[ {}].is_row\t{:x?}", 14 - i, state);
+        observer.observe(nr - i, Step::ISRow, &flatten_state_to_block(*state));
 
         inverse_mix_columns(state);
-        println!("round[ {}].im_col\t{:x?}", 14 - i, state);
-
-        println!(
-            "round[ {}].ik_sch\t{:x?}",
-            14 - i,
-            [
-                round_keys[4 * i],
-                round_keys[4 * i + 1],
-                round_keys[4 * i + 2],
-                round_keys[4 * i + 3],
-            ]
-        );
+        observer.observe(nr - i, Step::IMCol, &flatten_state_to_block(*state));
 
-        *state = add_round_key(
-            *state,
-            [
-                round_keys[4 * i],
-                round_keys[4 * i + 1],
-                round_keys[4 * i + 2],
-                round_keys[4 * i + 3],
-            ],
-        );
+        observer.observe(nr - i, Step::IKSch, &round_key_to_block(round_key(i)));
+        *state = add_round_key(*state, round_key(i));
     }
-    println!("round[ 14].istart\t{:x?}", state);
+    observer.observe(nr, Step::IStart, &flatten_state_to_block(*state));
 
     inverse_sub_bytes(state);
-    println!("round[ 14].is_box\t{:x?}", state);
+    observer.observe(nr, Step::ISBox, &flatten_state_to_block(*state));
 
     inverse_shift_rows(state);
-    println!("round[ 14].is_row\t{:x?}", state);
-
-    println!(
-        "round[ 14].ik_sch\t{:x?}",
-        [round_keys[0], round_keys[1], round_keys[2], round_keys[3]]
-    );
+    observer.observe(nr, Step::ISRow, &flatten_state_to_block(*state));
 
     // Last AddRoundKey with the first round key
-    *state = add_round_key(
-        *state,
-        [round_keys[0], round_keys[1], round_keys[2], round_keys[3]],
-    );
-    println!("round[ 14].ioutput\t{:x?}", state);
+    observer.observe(nr, Step::IKSch, &round_key_to_block(round_key(0)));
+    *state = add_round_key(*state, round_key(0));
+
+    observer.observe(nr, Step::IOutput, &flatten_state_to_block(*state));
 }
 
 #[cfg(test)]
@@ -155,7 +133,7 @@ mod tests {
             0x60, 0x89,
         ];
 
-        let round_keys = inverse_key_expansion(key);
+        let round_keys = inverse_key_expansion(&key);
         let mut state = expand_block_to_state(encrypted_data);
 
         state = add_round_key(
@@ -258,9 +236,9 @@ mod tests {
 
         println!("Cipher: {:x?}\nKey: {:x?}", ciphertext, key);
 
-        let round_keys = inverse_key_expansion(key);
+        let round_keys = inverse_key_expansion(&key);
         let mut state = expand_block_to_state(ciphertext);
-        perform_inverse_rounds(&mut state, &round_keys);
+        perform_inverse_rounds(&mut state, &round_keys, &mut ());
         let output = flatten_state_to_block(state);
         assert_eq!(
             output, expected,
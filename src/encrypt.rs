@@ -1,126 +1,117 @@
-use std::io::{self, Read, Write};
+use std::io::{Read, Write};
 
+use crate::cipher::Aes256;
+use crate::cts::{self, Variant};
+use crate::error::Result;
+use crate::io_util::read_full;
+use crate::key::Key256;
 use crate::shared::{
-    add_round_key, expand_block_to_state, flatten_state_to_block, key_expansion, mix_columns,
-    shift_rows, sub_bytes_state,
+    add_round_key, flatten_state_to_block, mix_columns, round_key_to_block, shift_rows,
+    sub_bytes_state,
 };
-
-pub fn encrypt_block(data: &[u8; 16], key: &[u8; 32]) -> [u8; 16] {
-    let round_keys = key_expansion(*key);
-    let mut output: [u8; 16] = [0; 16];
-
-    // Copy input data to output to use it as the state
-    output.copy_from_slice(data);
-    let mut state = expand_block_to_state(output);
-
-    // Perform the encryption rounds
-    perform_rounds(&mut state, &round_keys);
-    output = flatten_state_to_block(state);
-
-    return output;
-}
+use crate::trace::{RoundObserver, Step};
 
 const BLOCK_SIZE: usize = 16;
 
+/// Plaintext within this many bytes of the end may be part of the stolen final blocks, so it is
+/// held back until the end of the stream is reached.
+const HELD_BACK: usize = 2 * BLOCK_SIZE;
+
 /// Encrypts data from the input stream and writes to the output stream without padding.
-pub fn encrypt_stream<R: Read, W: Write>(
-    mut reader: R,
-    mut writer: W,
-    key: &[u8; 32],
-) -> io::Result<()> {
-    let mut buffer = [0u8; BLOCK_SIZE];
-    let mut read_size = 0;
-
-    while {
-        read_size = reader.read(&mut buffer)?;
-        read_size == BLOCK_SIZE
-    } {
-        let encrypted = encrypt_block(&buffer, key);
-        writer.write_all(&encrypted)?;
+///
+/// The output is exactly as long as the input: a partial final block is handled with ECB
+/// ciphertext stealing (CS2), so block-aligned input encrypts exactly as plain ECB. Input
+/// shorter than a single block cannot be encrypted this way and is rejected.
+pub fn encrypt_stream<R: Read, W: Write>(mut reader: R, mut writer: W, key: &Key256) -> Result<()> {
+    let cipher = Aes256::new(key);
+    let mut buffer = [0u8; 64 * BLOCK_SIZE];
+    let mut plaintext = Vec::with_capacity(buffer.len() + HELD_BACK + BLOCK_SIZE);
+
+    loop {
+        let read_size = read_full(&mut reader, &mut buffer)?;
+        if read_size == 0 {
+            break;
+        }
+
+        plaintext.extend_from_slice(&buffer[..read_size]);
+
+        // Encrypt every whole block that still leaves at least HELD_BACK bytes behind it, since
+        // the final block may need to steal from the one before it.
+        let ready = plaintext.len().saturating_sub(HELD_BACK) / BLOCK_SIZE * BLOCK_SIZE;
+        cipher.encrypt_blocks(plaintext[..ready].as_chunks_mut().0);
+        writer.write_all(&plaintext[..ready])?;
+        plaintext.drain(..ready);
     }
 
-    if read_size > 0 {
-        let encrypted = encrypt_block(&buffer, key);
-        writer.write_all(&encrypted[..read_size])?;
+    if plaintext.is_empty() {
+        return Ok(());
     }
 
+    // Input shorter than a block is rejected here with `Error::InputTooShort`.
+    cts::ecb_encrypt(&cipher, &mut plaintext, Variant::Cs2)?;
+    writer.write_all(&plaintext)?;
+
     Ok(())
 }
 
-fn perform_rounds(state: &mut [[u8; 4]; 4], round_keys: &[u32; 60]) {
-    println!("round[ 0].input\t{:x?}", state);
-    println!(
-        "round[ 0].k_sch\t{:x?}",
-        [round_keys[0], round_keys[1], round_keys[2], round_keys[3]]
-    );
+/// Runs the cipher over `state`; the number of rounds is implied by the length of `round_keys`.
+///
+/// Every intermediate value is reported to `observer` using the FIPS-197 Appendix C names.
+pub(crate) fn perform_rounds<O: RoundObserver>(
+    state: &mut [[u8; 4]; 4],
+    round_keys: &[u32],
+    observer: &mut O,
+) {
+    let nr = round_keys.len() / 4 - 1;
+    let round_key = |i: usize| {
+        [
+            round_keys[4 * i],
+            round_keys[4 * i + 1],
+            round_keys[4 * i + 2],
+            round_keys[4 * i + 3],
+        ]
+    };
+
+    observer.observe(0, Step::Input, &flatten_state_to_block(*state));
+    observer.observe(0, Step::KSch, &round_key_to_block(round_key(0)));
 
-    *state = add_round_key(
-        *state,
-        [round_keys[0], round_keys[1], round_keys[2], round_keys[3]],
-    );
+    *state = add_round_key(*state, round_key(0));
 
-    for i in 1..14 {
-        // Do 14 rounds for AES-256
-        println!("round[ {}].start\t{:x?}", i, state);
+    for i in 1..nr {
+        // Do 10, 12 or 14 rounds depending on the key size
+        observer.observe(i, Step::Start, &flatten_state_to_block(*state));
 
         sub_bytes_state(state);
+        observer.observe(i, Step::SBox, &flatten_state_to_block(*state));
 
-        println!("round[ {}].s_box\t{:x?}", i, state);
         shift_rows(state);
-        println!("round[ {}].s_row\t{:x?}", i, state);
+        observer.observe(i, Step::SRow, &flatten_state_to_block(*state));
 
         mix_columns(state); // Not applied in the last round
-        println!("round[ {}].m_col\t{:x?}", i, state);
+        observer.observe(i, Step::MCol, &flatten_state_to_block(*state));
 
-        *state = add_round_key(
-            *state,
-            [
-                round_keys[4 * i],
-                round_keys[4 * i + 1],
-                round_keys[4 * i + 2],
-                round_keys[4 * i + 3],
-            ],
-        );
-        println!(
-            "round[ {}].k_sch\t{:x?}",
-            i,
-            [
-                round_keys[4 * i],
-                round_keys[4 * i + 1],
-                round_keys[4 * i + 2],
-                round_keys[4 * i + 3],
-            ]
-        );
+        observer.observe(i, Step::KSch, &round_key_to_block(round_key(i)));
+        *state = add_round_key(*state, round_key(i));
     }
-    println!("round[ 14].start\t{:x?}", state);
+    observer.observe(nr, Step::Start, &flatten_state_to_block(*state));
 
     // Final round (no mix columns)
     sub_bytes_state(state);
-
-    println!("round[ 14].s_box\t{:x?}", state);
+    observer.observe(nr, Step::SBox, &flatten_state_to_block(*state));
 
     shift_rows(state);
+    observer.observe(nr, Step::SRow, &flatten_state_to_block(*state));
 
-    println!("round[ 14].s_row\t{:x?}", state);
+    observer.observe(nr, Step::KSch, &round_key_to_block(round_key(nr)));
+    *state = add_round_key(*state, round_key(nr));
 
-    println!("round[ 14].k_sch\t{:x?}", state);
-
-    *state = add_round_key(
-        *state,
-        [
-            round_keys[4 * 14],
-            round_keys[4 * 14 + 1],
-            round_keys[4 * 14 + 2],
-            round_keys[4 * 14 + 3],
-        ],
-    );
-
-    println!("round[ 14].start\t{:x?}", state);
+    observer.observe(nr, Step::Output, &flatten_state_to_block(*state));
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::shared::{expand_block_to_state, key_expansion};
 
     #[test]
     fn test_encrypt_first_round() {
@@ -134,7 +125,7 @@ mod test {
             0xEE, 0xFF,
         ];
 
-        let round_keys = key_expansion(key);
+        let round_keys = key_expansion(&key);
         let mut state = expand_block_to_state(data);
 
         // Initial AddRoundKey
@@ -227,9 +218,9 @@ mod test {
 
         println!("Plaintext: {:x?}\nKey: {:x?}", data, key);
 
-        let round_keys = key_expansion(key);
+        let round_keys = key_expansion(&key);
         let mut state = expand_block_to_state(data);
-        perform_rounds(&mut state, &round_keys);
+        perform_rounds(&mut state, &round_keys, &mut ());
         let output = flatten_state_to_block(state);
         assert_eq!(
             output, expected,
@@ -0,0 +1,135 @@
+// This file holds the error type returned by every fallible public function in the crate.
+
+use std::fmt;
+use std::io;
+
+/// Everything that can go wrong while encrypting or decrypting.
+#[derive(Debug)]
+pub enum Error {
+    /// Reading from or writing to a stream failed.
+    Io(io::Error),
+    /// A key was not a valid length for the cipher. Holds the length that was given.
+    InvalidKeyLength(usize),
+    /// An IV or nonce was not a valid length for the mode. Holds the length that was given.
+    InvalidIvLength(usize),
+    /// An authentication tag length is not allowed by the mode. Holds the length that was given.
+    InvalidTagLength(usize),
+    /// A key was rejected as unsafe for the mode, such as XTS data and tweak keys that are equal.
+    WeakKey,
+    /// A sector size was not usable for the mode. Holds the size that was given.
+    InvalidSectorSize(usize),
+    /// Plaintext was too short to encrypt with ciphertext stealing. Holds the length that was
+    /// given.
+    InputTooShort(usize),
+    /// Input was longer than the mode can process with the given parameters. Holds the length
+    /// that was given.
+    InputTooLong(usize),
+    /// The padding on the final block was malformed, usually because of a wrong key or a
+    /// corrupted message.
+    BadPadding,
+    /// An authentication tag did not match the message.
+    AuthenticationFailed,
+    /// The ciphertext ended early: it is missing its header or tag, or is not a whole number of
+    /// blocks.
+    TruncatedInput,
+    /// A stream header carried a format version this crate cannot read.
+    UnsupportedVersion(u8),
+}
+
+/// A `Result` whose error is [`Error`].
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// The closest `io::ErrorKind`, used when the error has to travel through `io::Error`.
+    fn kind(&self) -> io::ErrorKind {
+        match self {
+            Error::Io(error) => error.kind(),
+            Error::InvalidKeyLength(_)
+            | Error::InvalidIvLength(_)
+            | Error::InvalidTagLength(_)
+            | Error::WeakKey
+            | Error::InvalidSectorSize(_)
+            | Error::InputTooShort(_)
+            | Error::InputTooLong(_) => io::ErrorKind::InvalidInput,
+            Error::BadPadding | Error::AuthenticationFailed | Error::UnsupportedVersion(_) => {
+                io::ErrorKind::InvalidData
+            }
+            Error::TruncatedInput => io::ErrorKind::UnexpectedEof,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(error) => write!(f, "I/O error: {}", error),
+            Error::InvalidKeyLength(len) => write!(f, "invalid key length: {} bytes", len),
+            Error::InvalidIvLength(len) => write!(f, "invalid IV or nonce length: {} bytes", len),
+            Error::InvalidTagLength(len) => write!(f, "invalid tag length: {} bytes", len),
+            Error::WeakKey => write!(f, "key is not allowed for this mode"),
+            Error::InvalidSectorSize(size) => write!(f, "invalid sector size: {} bytes", size),
+            Error::InputTooShort(len) => write!(f, "input is too short: {} bytes", len),
+            Error::InputTooLong(len) => write!(f, "input is too long: {} bytes", len),
+            Error::BadPadding => write!(f, "invalid padding"),
+            Error::AuthenticationFailed => write!(f, "authentication failed"),
+            Error::TruncatedInput => write!(f, "ciphertext is truncated"),
+            Error::UnsupportedVersion(version) => {
+                write!(f, "unsupported format version {}", version)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// Recovers an [`Error`] that was carried through an `io::Error`, so errors raised inside the
+/// `io::Read`/`io::Write` adapters keep their variant.
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        if error.get_ref().is_some_and(|inner| inner.is::<Error>()) {
+            return *error.into_inner().unwrap().downcast::<Error>().unwrap();
+        }
+        Error::Io(error)
+    }
+}
+
+/// Carries an [`Error`] through APIs that must return `io::Error`, such as the `io::Read` and
+/// `io::Write` adapters. I/O errors are unwrapped; everything else is wrapped with the closest
+/// `io::ErrorKind` and can be recovered with `Error::from`.
+impl From<Error> for io::Error {
+    fn from(error: Error) -> Self {
+        match error {
+            Error::Io(error) => error,
+            error => io::Error::new(error.kind(), error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_round_trip_through_io_error() {
+        let error: io::Error = Error::BadPadding.into();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(matches!(Error::from(error), Error::BadPadding));
+
+        let error: io::Error = Error::TruncatedInput.into();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+        assert!(matches!(Error::from(error), Error::TruncatedInput));
+
+        let error: io::Error = Error::Io(io::ErrorKind::BrokenPipe.into()).into();
+        assert_eq!(error.kind(), io::ErrorKind::BrokenPipe);
+        assert!(
+            matches!(Error::from(error), Error::Io(e) if e.kind() == io::ErrorKind::BrokenPipe)
+        );
+    }
+}
@@ -0,0 +1,480 @@
+// This file holds the Galois/Counter Mode (GCM) authenticated encryption from NIST SP 800-38D.
+
+use zeroize::Zeroize;
+
+use crate::cipher::BlockCipher;
+use crate::ctr;
+use crate::error::{Error, Result};
+
+const BLOCK_SIZE: usize = 16;
+
+/// The longest plaintext SP 800-38D allows, 2^39 - 256 bits. The 32-bit counter would wrap and
+/// reuse keystream past it.
+const MAX_PAYLOAD_LEN: u64 = (1 << 36) - 32;
+
+/// The tag lengths, in bytes, permitted by SP 800-38D.
+const TAG_LENGTHS: [usize; 7] = [4, 8, 12, 13, 14, 15, 16];
+
+/// GCM authenticated encryption over a block cipher.
+///
+/// Sealed messages are the ciphertext followed by the authentication tag. The hash subkey is
+/// wiped when the instance is dropped.
+pub struct Gcm<C> {
+    cipher: C,
+    hash_key: u128,
+    tag_len: usize,
+}
+
+impl<C: BlockCipher> Gcm<C> {
+    /// Creates a GCM instance that produces full 128-bit tags.
+    pub fn new(cipher: C) -> Self {
+        let hash_key = u128::from_be_bytes(cipher.encrypt_block(&[0; BLOCK_SIZE]));
+        Gcm {
+            cipher,
+            hash_key,
+            tag_len: BLOCK_SIZE,
+        }
+    }
+
+    /// Creates a GCM instance that produces truncated tags of `tag_len` bytes.
+    ///
+    /// Tags shorter than 12 bytes are only safe for short messages under the limits in
+    /// SP 800-38D Appendix C.
+    pub fn with_tag_len(cipher: C, tag_len: usize) -> Result<Self> {
+        if !TAG_LENGTHS.contains(&tag_len) {
+            return Err(Error::InvalidTagLength(tag_len));
+        }
+
+        let mut gcm = Gcm::new(cipher);
+        gcm.tag_len = tag_len;
+        Ok(gcm)
+    }
+
+    /// The length of the tag appended to sealed messages.
+    pub fn tag_len(&self) -> usize {
+        self.tag_len
+    }
+
+    /// Encrypts and authenticates `plaintext`, also authenticating `aad`. Returns the ciphertext
+    /// followed by the tag.
+    ///
+    /// Fails with [`Error::InputTooLong`] if the plaintext is longer than 2^36 - 32 bytes.
+    pub fn seal(&self, nonce: &[u8], aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+        let j0 = self.pre_counter_block(nonce)?;
+        check_payload_len(plaintext.len())?;
+
+        let mut output = plaintext.to_vec();
+        self.gctr(&increment_32(&j0), &mut output);
+
+        let tag = self.tag(&j0, aad, &output);
+        output.extend_from_slice(&tag[..self.tag_len]);
+
+        Ok(output)
+    }
+
+    /// Verifies and decrypts a message produced by [`Gcm::seal`].
+    ///
+    /// Nothing is decrypted unless the tag matches, and the comparison takes the same time
+    /// wherever the tags differ.
+    pub fn open(&self, nonce: &[u8], aad: &[u8], sealed: &[u8]) -> Result<Vec<u8>> {
+        if sealed.len() < self.tag_len {
+            return Err(Error::TruncatedInput);
+        }
+
+        let j0 = self.pre_counter_block(nonce)?;
+        let (ciphertext, received_tag) = sealed.split_at(sealed.len() - self.tag_len);
+        check_payload_len(ciphertext.len())?;
+
+        let expected_tag = self.tag(&j0, aad, ciphertext);
+        if !constant_time_eq(&expected_tag[..self.tag_len], received_tag) {
+            return Err(Error::AuthenticationFailed);
+        }
+
+        let mut output = ciphertext.to_vec();
+        self.gctr(&increment_32(&j0), &mut output);
+
+        Ok(output)
+    }
+
+    /// Derives J0 from the nonce: 96-bit nonces are used directly, any other length is hashed.
+    fn pre_counter_block(&self, nonce: &[u8]) -> Result<[u8; BLOCK_SIZE]> {
+        if nonce.is_empty() {
+            return Err(Error::InvalidIvLength(0));
+        }
+
+        if nonce.len() == 12 {
+            let mut j0 = [0; BLOCK_SIZE];
+            j0[..12].copy_from_slice(nonce);
+            j0[15] = 1;
+            return Ok(j0);
+        }
+
+        let mut ghash = Ghash::new(self.hash_key);
+        ghash.update_padded(nonce);
+        ghash.update_lengths(0, nonce.len() as u64 * 8);
+        Ok(ghash.finish())
+    }
+
+    fn tag(&self, j0: &[u8; BLOCK_SIZE], aad: &[u8], ciphertext: &[u8]) -> [u8; BLOCK_SIZE] {
+        let mut ghash = Ghash::new(self.hash_key);
+        ghash.update_padded(aad);
+        ghash.update_padded(ciphertext);
+        ghash.update_lengths(aad.len() as u64 * 8, ciphertext.len() as u64 * 8);
+
+        let mut tag = ghash.finish();
+        for (t, k) in tag.iter_mut().zip(self.cipher.encrypt_block(j0)) {
+            *t ^= k;
+        }
+        tag
+    }
+
+    /// Applies the GCTR keystream starting at `counter`, incrementing only its low 32 bits.
+    fn gctr(&self, counter: &[u8; BLOCK_SIZE], data: &mut [u8]) {
+        ctr::xor_keystream(&self.cipher, counter, increment_32, data);
+    }
+}
+
+impl<C> Drop for Gcm<C> {
+    fn drop(&mut self) {
+        self.hash_key.zeroize();
+    }
+}
+
+/// The GHASH universal hash, accumulating 128-bit blocks.
+struct Ghash {
+    hash_key: u128,
+    accumulator: u128,
+}
+
+impl Ghash {
+    fn new(hash_key: u128) -> Self {
+        Ghash {
+            hash_key,
+            accumulator: 0,
+        }
+    }
+
+    /// Absorbs `data`, zero-padding the final block.
+    fn update_padded(&mut self, data: &[u8]) {
+        for chunk in data.chunks(BLOCK_SIZE) {
+            let mut block = [0; BLOCK_SIZE];
+            block[..chunk.len()].copy_from_slice(chunk);
+            self.update_block(&block);
+        }
+    }
+
+    /// Absorbs the final length block, both lengths given in bits.
+    fn update_lengths(&mut self, first_bits: u64, second_bits: u64) {
+        let mut block = [0; BLOCK_SIZE];
+        block[..8].copy_from_slice(&first_bits.to_be_bytes());
+        block[8..].copy_from_slice(&second_bits.to_be_bytes());
+        self.update_block(&block);
+    }
+
+    fn update_block(&mut self, block: &[u8; BLOCK_SIZE]) {
+        self.accumulator = gf128_mul(
+            self.accumulator ^ u128::from_be_bytes(*block),
+            self.hash_key,
+        );
+    }
+
+    fn finish(self) -> [u8; BLOCK_SIZE] {
+        self.accumulator.to_be_bytes()
+    }
+}
+
+impl Drop for Ghash {
+    fn drop(&mut self) {
+        self.hash_key.zeroize();
+        self.accumulator.zeroize();
+    }
+}
+
+/// Multiplies two elements of GF(2^128) using the bit-reflected convention of GCM, where the most
+/// significant bit of the integer is the coefficient of x^0.
+///
+/// Every iteration does the same work regardless of the operands.
+pub(crate) fn gf128_mul(x: u128, y: u128) -> u128 {
+    const R: u128 = 0xE1 << 120;
+
+    let mut z = 0;
+    let mut v = y;
+    for i in 0..128 {
+        let bit = (x >> (127 - i)) & 1;
+        z ^= v & bit.wrapping_neg();
+        let carry = v & 1;
+        v = (v >> 1) ^ (R & carry.wrapping_neg());
+    }
+    z
+}
+
+fn check_payload_len(len: usize) -> Result<()> {
+    if len as u64 > MAX_PAYLOAD_LEN {
+        return Err(Error::InputTooLong(len));
+    }
+    Ok(())
+}
+
+/// Increments the rightmost 32 bits of the block modulo 2^32.
+fn increment_32(block: &[u8; BLOCK_SIZE]) -> [u8; BLOCK_SIZE] {
+    let mut output = *block;
+    let low = u32::from_be_bytes(block[12..].try_into().unwrap()).wrapping_add(1);
+    output[12..].copy_from_slice(&low.to_be_bytes());
+    output
+}
+
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    let mut difference = 0;
+    for (x, y) in a.iter().zip(b) {
+        difference |= x ^ y;
+    }
+    difference == 0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cipher::{Aes128, Aes256};
+    use crate::key::Key;
+    use crate::test_util::{check_aead, check_rejects_tampering, hex, hex_array};
+
+    const KEY_128: &str = "feffe9928665731c6d6a8f9467308308";
+    const KEY_256: &str = "feffe9928665731c6d6a8f9467308308 feffe9928665731c6d6a8f9467308308";
+    const PLAINTEXT: &str = "
+        d9313225f88406e5a55909c5aff5269a 86a7a9531534f7da2e4c303d8a318a72
+        1c3c0c95956809532fcf0e2449a6b525 b16aedf5aa0de657ba637b391aafd255";
+    const AAD: &str = "feedfacedeadbeeffeedfacedeadbeef abaddad2";
+    const NONCE_96: &str = "cafebabefacedbaddecaf888";
+    const NONCE_64: &str = "cafebabefacedbad";
+    const NONCE_480: &str = "
+        9313225df88406e555909c5aff5269aa 6a7a9538534f7da1e4c303d2a318a728
+        c3c0c95156809539fcf0e2429a6b5254 16aedbf5a0de6a57a637b39b";
+
+    /// Checks a vector from the GCM specification, which lists the ciphertext and tag apart.
+    fn check<C: BlockCipher>(
+        cipher: C,
+        nonce: &str,
+        aad: &str,
+        plaintext: &[u8],
+        ciphertext: &str,
+        tag: &str,
+    ) {
+        let mut expected = hex(ciphertext);
+        expected.extend(hex(tag));
+        check_aead(
+            &Gcm::new(cipher),
+            &hex(nonce),
+            &hex(aad),
+            plaintext,
+            &expected,
+        );
+    }
+
+    #[test]
+    fn test_gcm_spec_aes128_zero_key() {
+        // Test cases 1 and 2 of the GCM specification.
+        let cipher = Aes128::new(&Key::new([0; 16]));
+        let nonce = "000000000000000000000000";
+        check(
+            &cipher,
+            nonce,
+            "",
+            &[],
+            "",
+            "58e2fccefa7e3061367f1d57a4e7455a",
+        );
+        check(
+            &cipher,
+            nonce,
+            "",
+            &[0; 16],
+            "0388dace60b6a392f328c2b971b2fe78",
+            "ab6e47d42cec13bdf53a67b21257bddf",
+        );
+    }
+
+    #[test]
+    fn test_gcm_spec_aes128() {
+        // Test cases 3 to 6 of the GCM specification.
+        let cipher = Aes128::new(&Key::new(hex_array(KEY_128)));
+        let plaintext = hex(PLAINTEXT);
+
+        check(
+            &cipher,
+            NONCE_96,
+            "",
+            &plaintext,
+            "42831ec2217774244b7221b784d0d49c e3aa212f2c02a4e035c17e2329aca12e
+             21d514b25466931c7d8f6a5aac84aa05 1ba30b396a0aac973d58e091473f5985",
+            "4d5c2af327cd64a62cf35abd2ba6fab4",
+        );
+        check(
+            &cipher,
+            NONCE_96,
+            AAD,
+            &plaintext[..60],
+            "42831ec2217774244b7221b784d0d49c e3aa212f2c02a4e035c17e2329aca12e
+             21d514b25466931c7d8f6a5aac84aa05 1ba30b396a0aac973d58e091",
+            "5bc94fbc3221a5db94fae95ae7121a47",
+        );
+        check(
+            &cipher,
+            NONCE_64,
+            AAD,
+            &plaintext[..60],
+            "61353b4c2806934a777ff51fa22a4755 699b2a714fcdc6f83766e5f97b6c7423
+             73806900e49f24b22b097544d4896b42 4989b5e1ebac0f07c23f4598",
+            "3612d2e79e3b0785561be14aaca2fccb",
+        );
+        check(
+            &cipher,
+            NONCE_480,
+            AAD,
+            &plaintext[..60],
+            "8ce24998625615b603a033aca13fb894 be9112a5c3a211a8ba262a3cca7e2ca7
+             01e4a9a4fba43c90ccdcb281d48c7c6f d62875d2aca417034c34aee5",
+            "619cc5aefffe0bfa462af43c1699d050",
+        );
+    }
+
+    #[test]
+    fn test_gcm_spec_aes256_zero_key() {
+        // Test cases 13 and 14 of the GCM specification.
+        let cipher = Aes256::new(&Key::new([0; 32]));
+        let nonce = "000000000000000000000000";
+        check(
+            &cipher,
+            nonce,
+            "",
+            &[],
+            "",
+            "530f8afbc74536b9a963b4f1c4cb738b",
+        );
+        check(
+            &cipher,
+            nonce,
+            "",
+            &[0; 16],
+            "cea7403d4d606b6e074ec5d3baf39d18",
+            "d0d1c8a799996bf0265b98b5d48ab919",
+        );
+    }
+
+    #[test]
+    fn test_gcm_spec_aes256() {
+        // Test cases 15 to 18 of the GCM specification.
+        let cipher = Aes256::new(&Key::new(hex_array(KEY_256)));
+        let plaintext = hex(PLAINTEXT);
+
+        check(
+            &cipher,
+            NONCE_96,
+            "",
+            &plaintext,
+            "522dc1f099567d07f47f37a32a84427d 643a8cdcbfe5c0c97598a2bd2555d1aa
+             8cb08e48590dbb3da7b08b1056828838 c5f61e6393ba7a0abcc9f662898015ad",
+            "b094dac5d93471bdec1a502270e3cc6c",
+        );
+        check(
+            &cipher,
+            NONCE_96,
+            AAD,
+            &plaintext[..60],
+            "522dc1f099567d07f47f37a32a84427d 643a8cdcbfe5c0c97598a2bd2555d1aa
+             8cb08e48590dbb3da7b08b1056828838 c5f61e6393ba7a0abcc9f662",
+            "76fc6ece0f4e1768cddf8853bb2d551b",
+        );
+        check(
+            &cipher,
+            NONCE_64,
+            AAD,
+            &plaintext[..60],
+            "c3762df1ca787d32ae47c13bf19844cb af1ae14d0b976afac52ff7d79bba9de0
+             feb582d33934a4f0954cc2363bc73f78 62ac430e64abe499f47c9b1f",
+            "3a337dbf46a792c45e454913fe2ea8f2",
+        );
+        check(
+            &cipher,
+            NONCE_480,
+            AAD,
+            &plaintext[..60],
+            "5a8def2f0c9e53f1f75d7853659e2a20 eeb2b22aafde6419a058ab4f6f746bf4
+             0fc0c3b780f244452da3ebf1c5d82cde a2418997200ef82e44ae7e3f",
+            "a44a8266ee1c8eb0c8b5d4cf5ae9f19a",
+        );
+    }
+
+    #[test]
+    fn test_truncated_tags() {
+        let cipher = Aes256::new(&Key::new(hex_array(KEY_256)));
+        let (nonce, aad, plaintext) = (hex(NONCE_96), hex(AAD), hex(PLAINTEXT));
+        let full = Gcm::new(&cipher).seal(&nonce, &aad, &plaintext).unwrap();
+
+        for tag_len in TAG_LENGTHS {
+            let gcm = Gcm::with_tag_len(&cipher, tag_len).unwrap();
+            let sealed = gcm.seal(&nonce, &aad, &plaintext).unwrap();
+            assert_eq!(sealed[..], full[..plaintext.len() + tag_len]);
+            assert_eq!(gcm.open(&nonce, &aad, &sealed).unwrap(), plaintext);
+        }
+
+        for tag_len in [0, 3, 5, 11, 17] {
+            assert!(matches!(
+                Gcm::with_tag_len(&cipher, tag_len),
+                Err(Error::InvalidTagLength(n)) if n == tag_len
+            ));
+        }
+    }
+
+    #[test]
+    fn test_open_rejects_tampering() {
+        let gcm = Gcm::new(Aes256::new(&Key::new(hex_array(KEY_256))));
+        let (nonce, aad, plaintext) = (hex(NONCE_96), hex(AAD), hex(PLAINTEXT));
+        let sealed = check_rejects_tampering(&gcm, &nonce, &aad, &plaintext);
+
+        assert!(gcm.open(&hex(NONCE_64), &aad, &sealed).is_err());
+        assert!(matches!(
+            gcm.open(&nonce, &aad, &sealed[..15]),
+            Err(Error::TruncatedInput)
+        ));
+    }
+
+    #[test]
+    fn test_increment_32_wraps_low_word_only() {
+        let mut block = [0xff; 16];
+        block[11] = 0x07;
+        let incremented = increment_32(&block);
+        assert_eq!(incremented[..12], block[..12]);
+        assert_eq!(incremented[12..], [0; 4]);
+    }
+
+    #[test]
+    fn test_empty_nonce_rejected() {
+        let gcm = Gcm::new(Aes128::new(&Key::new([0; 16])));
+        assert!(matches!(
+            gcm.seal(&[], &[], b"data"),
+            Err(Error::InvalidIvLength(0))
+        ));
+        assert!(matches!(
+            gcm.open(&[], &[], &[0; 20]),
+            Err(Error::InvalidIvLength(0))
+        ));
+    }
+
+    #[test]
+    fn test_payload_length_limit() {
+        let limit = MAX_PAYLOAD_LEN as usize;
+        assert!(check_payload_len(limit).is_ok());
+        assert!(matches!(
+            check_payload_len(limit + 1),
+            Err(Error::InputTooLong(n)) if n == limit + 1
+        ));
+
+        // At the limit the payload takes 2^32 - 2 counter blocks after J0, so the 32-bit
+        // counter never wraps back around to J0.
+        assert_eq!(MAX_PAYLOAD_LEN, ((1u64 << 32) - 2) * BLOCK_SIZE as u64);
+    }
+}
@@ -0,0 +1,321 @@
+// This file holds AES-256-GCM-SIV from RFC 8452, a nonce-misuse-resistant authenticated
+// encryption built from POLYVAL and AES-CTR with per-nonce keys.
+
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::cipher::Aes256;
+use crate::ctr;
+use crate::error::{Error, Result};
+use crate::gcm::{constant_time_eq, gf128_mul};
+use crate::key::{Key, Key256};
+
+const BLOCK_SIZE: usize = 16;
+
+const NONCE_LEN: usize = 12;
+
+/// The longest plaintext or associated data RFC 8452 allows, 2^36 bytes.
+const MAX_INPUT_LEN: u64 = 1 << 36;
+
+/// AES-256-GCM-SIV authenticated encryption.
+///
+/// Every nonce derives its own authentication and encryption keys, and the tag doubles as the
+/// CTR IV. Repeating a nonce only reveals whether two messages were identical, instead of
+/// breaking confidentiality as it does with GCM. Nonces are 12 bytes long and sealed messages
+/// are the ciphertext followed by the 16-byte tag.
+pub struct Aes256GcmSiv {
+    key_generating_cipher: Aes256,
+}
+
+impl Aes256GcmSiv {
+    pub fn new(key: &Key256) -> Self {
+        Aes256GcmSiv {
+            key_generating_cipher: Aes256::new(key),
+        }
+    }
+
+    /// Encrypts and authenticates `plaintext`, also authenticating `aad`. Returns the ciphertext
+    /// followed by the tag.
+    pub fn seal(&self, nonce: &[u8], aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+        let nonce = check_inputs(nonce, aad, plaintext.len())?;
+        let (hash_key, cipher) = self.derive_keys(nonce);
+
+        let tag = tag(&cipher, *hash_key, nonce, aad, plaintext);
+        let mut output = plaintext.to_vec();
+        ctr(&cipher, &tag, &mut output);
+        output.extend_from_slice(&tag);
+
+        Ok(output)
+    }
+
+    /// Verifies and decrypts a message produced by [`Aes256GcmSiv::seal`].
+    ///
+    /// The received tag is also the initial counter block, so the message is decrypted with it
+    /// first and the tag is then recomputed from the plaintext and the nonce's derived keys. The
+    /// plaintext is returned only if the two tags are equal, compared in constant time.
+    pub fn open(&self, nonce: &[u8], aad: &[u8], sealed: &[u8]) -> Result<Vec<u8>> {
+        if sealed.len() < BLOCK_SIZE {
+            return Err(Error::TruncatedInput);
+        }
+
+        let (ciphertext, received_tag) = sealed.split_at(sealed.len() - BLOCK_SIZE);
+        let received_tag: &[u8; BLOCK_SIZE] = received_tag.try_into().unwrap();
+        let nonce = check_inputs(nonce, aad, ciphertext.len())?;
+        let (hash_key, cipher) = self.derive_keys(nonce);
+
+        // Until POLYVAL has confirmed the tag, `output` holds plaintext recovered under an
+        // unverified counter, so a mismatch zeroes it before returning the error.
+        let mut output = ciphertext.to_vec();
+        ctr(&cipher, received_tag, &mut output);
+        let expected_tag = tag(&cipher, *hash_key, nonce, aad, &output);
+
+        if !constant_time_eq(&expected_tag, received_tag) {
+            output.fill(0);
+            return Err(Error::AuthenticationFailed);
+        }
+
+        Ok(output)
+    }
+
+    /// Derives the message authentication key and the message encryption cipher for a nonce.
+    /// Each key is built from the first halves of successive encrypted counter blocks.
+    fn derive_keys(&self, nonce: &[u8; NONCE_LEN]) -> (Zeroizing<u128>, Aes256) {
+        let mut blocks = [[0; BLOCK_SIZE]; 6];
+        for (i, block) in blocks.iter_mut().enumerate() {
+            block[..4].copy_from_slice(&(i as u32).to_le_bytes());
+            block[4..].copy_from_slice(nonce);
+        }
+        self.key_generating_cipher.encrypt_blocks(&mut blocks);
+
+        let mut key_bytes = [0; 48];
+        for (half, block) in key_bytes.chunks_exact_mut(8).zip(&blocks) {
+            half.copy_from_slice(&block[..8]);
+        }
+        let hash_key = Zeroizing::new(polyval_key(key_bytes[..16].try_into().unwrap()));
+        let cipher = Aes256::new(&Key::new(key_bytes[16..].try_into().unwrap()));
+
+        blocks.zeroize();
+        key_bytes.zeroize();
+        (hash_key, cipher)
+    }
+}
+
+/// Checks the nonce length and the input lengths against the limits in RFC 8452.
+fn check_inputs<'a>(
+    nonce: &'a [u8],
+    aad: &[u8],
+    payload_len: usize,
+) -> Result<&'a [u8; NONCE_LEN]> {
+    let nonce = nonce
+        .try_into()
+        .map_err(|_| Error::InvalidIvLength(nonce.len()))?;
+
+    for len in [aad.len(), payload_len] {
+        if len as u64 > MAX_INPUT_LEN {
+            return Err(Error::InputTooLong(len));
+        }
+    }
+
+    Ok(nonce)
+}
+
+/// Computes the tag: POLYVAL over the AAD, the plaintext and their lengths, masked with the
+/// nonce and encrypted with the message encryption key.
+fn tag(
+    cipher: &Aes256,
+    hash_key: u128,
+    nonce: &[u8; NONCE_LEN],
+    aad: &[u8],
+    plaintext: &[u8],
+) -> [u8; BLOCK_SIZE] {
+    let mut polyval = Polyval::new(hash_key);
+    polyval.update_padded(aad);
+    polyval.update_padded(plaintext);
+
+    let mut length_block = [0; BLOCK_SIZE];
+    length_block[..8].copy_from_slice(&(aad.len() as u64 * 8).to_le_bytes());
+    length_block[8..].copy_from_slice(&(plaintext.len() as u64 * 8).to_le_bytes());
+    polyval.update_block(&length_block);
+
+    let mut s = polyval.finish();
+    for (s, n) in s.iter_mut().zip(nonce) {
+        *s ^= n;
+    }
+    s[BLOCK_SIZE - 1] &= 0x7f;
+    cipher.encrypt_block(&s)
+}
+
+/// Applies the CTR keystream. The initial counter block is the tag with its top bit set, and
+/// only its first 32 bits, read as a little-endian integer, are incremented.
+fn ctr(cipher: &Aes256, tag: &[u8; BLOCK_SIZE], data: &mut [u8]) {
+    let mut counter = *tag;
+    counter[BLOCK_SIZE - 1] |= 0x80;
+    ctr::xor_keystream(cipher, &counter, increment_le32, data);
+}
+
+/// Increments the first 32 bits of a counter block as a little-endian integer, wrapping at 2^32.
+fn increment_le32(counter: &[u8; BLOCK_SIZE]) -> [u8; BLOCK_SIZE] {
+    let mut next = *counter;
+    let low = u32::from_le_bytes(next[..4].try_into().unwrap()).wrapping_add(1);
+    next[..4].copy_from_slice(&low.to_le_bytes());
+    next
+}
+
+/// Converts a POLYVAL key into the GHASH key that gives the same hash over byte-reversed blocks,
+/// as described in RFC 8452 Appendix A: the reversed key multiplied by x.
+fn polyval_key(key: &[u8; BLOCK_SIZE]) -> u128 {
+    const R: u128 = 0xE1 << 120;
+
+    let h = u128::from_le_bytes(*key);
+    (h >> 1) ^ (R & (h & 1).wrapping_neg())
+}
+
+/// The POLYVAL universal hash, computed with GHASH multiplication on byte-reversed blocks.
+struct Polyval {
+    hash_key: u128,
+    accumulator: u128,
+}
+
+impl Polyval {
+    fn new(hash_key: u128) -> Self {
+        Polyval {
+            hash_key,
+            accumulator: 0,
+        }
+    }
+
+    /// Absorbs `data`, zero-padding the final block. Each block is copied through a buffer that
+    /// is wiped afterwards, since `data` may be plaintext.
+    fn update_padded(&mut self, data: &[u8]) {
+        let mut block = Zeroizing::new([0; BLOCK_SIZE]);
+        for chunk in data.chunks(BLOCK_SIZE) {
+            block.fill(0);
+            block[..chunk.len()].copy_from_slice(chunk);
+            self.update_block(&block);
+        }
+    }
+
+    fn update_block(&mut self, block: &[u8; BLOCK_SIZE]) {
+        self.accumulator = gf128_mul(
+            self.accumulator ^ u128::from_le_bytes(*block),
+            self.hash_key,
+        );
+    }
+
+    fn finish(self) -> [u8; BLOCK_SIZE] {
+        self.accumulator.to_le_bytes()
+    }
+}
+
+impl Drop for Polyval {
+    fn drop(&mut self) {
+        self.hash_key.zeroize();
+        self.accumulator.zeroize();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::{check_aead, check_rejects_tampering, hex, hex_array};
+
+    /// Checks an RFC 8452 vector, given in hex as the appendix lists it.
+    fn check(key: &str, nonce: &str, aad: &str, plaintext: &str, sealed: &str) {
+        let gcm_siv = Aes256GcmSiv::new(&Key::new(hex_array(key)));
+        check_aead(
+            &gcm_siv,
+            &hex(nonce),
+            &hex(aad),
+            &hex(plaintext),
+            &hex(sealed),
+        );
+    }
+
+    const KEY: &str = "01000000000000000000000000000000 00000000000000000000000000000000";
+    const NONCE: &str = "030000000000000000000000";
+
+    #[test]
+    fn test_rfc8452_aes256_without_aad() {
+        // Appendix C.2.
+        check(KEY, NONCE, "", "", "07f5f4169bbf55a8400cd47ea6fd400f");
+        check(
+            KEY,
+            NONCE,
+            "",
+            "0200000000000000",
+            "42162cd555fa6e19 de10e90edcc04151a253a05ef38f0402",
+        );
+        check(
+            KEY,
+            NONCE,
+            "",
+            "02000000000000000000000000000000 03000000000000000000000000000000",
+            "f0c0c6725319284304f5ec0cc6970fb9 79fb0651ea8e9fefeb78a86fb6c1c68d
+             be227dce35257d6808b647ff9f097197",
+        );
+    }
+
+    #[test]
+    fn test_rfc8452_aes256_with_aad() {
+        // Appendix C.2.
+        check(
+            KEY,
+            NONCE,
+            "01",
+            "02000000000000000000000000000000 03000000000000000000000000000000
+             04000000000000000000000000000000",
+            "c67a1f0f567a5198aa1fcc8e3f213143 36f7f51ca8b1af61feac35a86416fa47
+             fbca3b5f749cdf564527f2314f42fe25 03332742b228c647173616cfd44c54eb",
+        );
+        check(
+            KEY,
+            NONCE,
+            "01000000",
+            "020000000000000000000000",
+            "88d147afb2d417cd4ea2b25f 3707645b90ee5f014e040e149f16977c",
+        );
+    }
+
+    #[test]
+    fn test_rfc8452_counter_wrap() {
+        // Appendix C.3: the tag starts the 32-bit counter at 0xffffffff, so it wraps to zero
+        // after the first block.
+        check(
+            "00000000000000000000000000000000 00000000000000000000000000000000",
+            "000000000000000000000000",
+            "",
+            "eb3640277c7ffd1303c7a542d02d3e4c 0000000000000000",
+            "18ce4f0b8cb4d0cac65fea8f79257b20 888e53e72299e56d
+             ffffffff000000000000000000000000",
+        );
+    }
+
+    #[test]
+    fn test_open_rejects_tampering() {
+        let gcm_siv = Aes256GcmSiv::new(&Key::new([7; 32]));
+        let nonce = [9; 12];
+        let plaintext: Vec<u8> = (0..300).map(|i| (i * 7) as u8).collect();
+        let sealed = check_rejects_tampering(&gcm_siv, &nonce, b"aad", &plaintext);
+
+        assert!(gcm_siv.open(&[8; 12], b"aad", &sealed).is_err());
+        assert!(matches!(
+            gcm_siv.open(&nonce, b"aad", &sealed[..15]),
+            Err(Error::TruncatedInput)
+        ));
+    }
+
+    #[test]
+    fn test_invalid_nonce_length() {
+        let gcm_siv = Aes256GcmSiv::new(&Key::new([7; 32]));
+        for nonce_len in [0, 8, 11, 13, 16] {
+            let nonce = vec![0; nonce_len];
+            assert!(matches!(
+                gcm_siv.seal(&nonce, &[], b"data"),
+                Err(Error::InvalidIvLength(n)) if n == nonce_len
+            ));
+            assert!(matches!(
+                gcm_siv.open(&nonce, &[], &[0; 20]),
+                Err(Error::InvalidIvLength(n)) if n == nonce_len
+            ));
+        }
+    }
+}
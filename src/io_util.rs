@@ -0,0 +1,92 @@
+// This file holds the I/O helpers shared by the streaming functions.
+
+use std::io::{self, Read, Write};
+
+use crate::error::Result;
+
+/// The size of the buffer used by [`process_stream`]: 64 cipher blocks.
+const STREAM_BUFFER_LEN: usize = 64 * 16;
+
+/// Reads until `buffer` is full or the reader is exhausted, returning the number of bytes read.
+///
+/// A single `read` call may legitimately return fewer bytes than requested (pipes, sockets and
+/// buffered readers all do), so a short count only means end of input once the reader returns 0.
+/// Interrupted reads are retried.
+pub fn read_full<R: Read>(reader: &mut R, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// Copies `reader` to `writer`, passing each chunk through `process` on the way. Used by the
+/// length-preserving stream modes, whose transform keeps its own state between chunks.
+pub fn process_stream<R: Read, W: Write>(
+    mut reader: R,
+    mut writer: W,
+    mut process: impl FnMut(&mut [u8]),
+) -> Result<()> {
+    let mut buffer = [0u8; STREAM_BUFFER_LEN];
+
+    loop {
+        let read_size = read_full(&mut reader, &mut buffer)?;
+        if read_size == 0 {
+            break;
+        }
+
+        process(&mut buffer[..read_size]);
+        writer.write_all(&buffer[..read_size])?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::ChunkedReader;
+
+    #[test]
+    fn test_read_full_across_short_reads() {
+        let data: Vec<u8> = (0..50).collect();
+        let mut reader = ChunkedReader::fixed(&data, 3).interrupting();
+        let mut buffer = [0u8; 16];
+
+        assert_eq!(read_full(&mut reader, &mut buffer).unwrap(), 16);
+        assert_eq!(buffer, data[..16]);
+        assert_eq!(read_full(&mut reader, &mut buffer).unwrap(), 16);
+        assert_eq!(read_full(&mut reader, &mut buffer).unwrap(), 16);
+        assert_eq!(read_full(&mut reader, &mut buffer).unwrap(), 2);
+        assert_eq!(buffer[..2], data[48..]);
+        assert_eq!(read_full(&mut reader, &mut buffer).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_process_stream_sees_every_byte_in_order() {
+        let data: Vec<u8> = (0..3000).map(|i| i as u8).collect();
+        let reader = ChunkedReader::random(&data, 5).interrupting();
+        let mut output = Vec::new();
+        let mut seen = 0usize;
+
+        process_stream(reader, &mut output, |chunk| {
+            for byte in chunk.iter_mut() {
+                assert_eq!(*byte, seen as u8);
+                *byte ^= 0xff;
+                seen += 1;
+            }
+        })
+        .unwrap();
+
+        assert_eq!(seen, data.len());
+        assert!(output
+            .iter()
+            .zip(&data)
+            .all(|(out, byte)| *out == byte ^ 0xff));
+    }
+}
@@ -0,0 +1,160 @@
+// This file holds the types that own secret key material and wipe it when they are dropped.
+
+use std::fmt;
+use std::ops::Deref;
+
+use rand::RngCore;
+use zeroize::Zeroize;
+
+use crate::error::{Error, Result};
+
+/// An `N`-byte AES key that is zeroed when dropped and never printed by `Debug`.
+///
+/// The wrapped bytes are only reachable through [`Key::as_bytes`], so the key is not copied
+/// around implicitly. Note that [`Key::new`] cannot wipe the array it is given; prefer
+/// [`Key::from_slice`] on a buffer you clear yourself, or [`Key::generate`].
+#[derive(Clone)]
+pub struct Key<const N: usize>([u8; N]);
+
+/// A 128-bit AES key.
+pub type Key128 = Key<16>;
+
+/// A 192-bit AES key.
+pub type Key192 = Key<24>;
+
+/// A 256-bit AES key.
+pub type Key256 = Key<32>;
+
+impl<const N: usize> Key<N> {
+    /// Wraps a key whose length is known at compile time. The caller's array is moved in as a
+    /// copy and is not wiped, so clear it yourself if it held secret bytes.
+    pub fn new(bytes: [u8; N]) -> Self {
+        Key(bytes)
+    }
+
+    /// Copies a key of unchecked length, such as one read from a file. Fails with
+    /// [`Error::InvalidKeyLength`] unless `bytes` is exactly `N` bytes long.
+    pub fn from_slice(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() != N {
+            return Err(Error::InvalidKeyLength(bytes.len()));
+        }
+
+        let mut key = Key([0; N]);
+        key.0.copy_from_slice(bytes);
+        Ok(key)
+    }
+
+    /// Generates a random key from the operating system's CSPRNG.
+    pub fn generate() -> Self {
+        let mut key = Key([0; N]);
+        rand::rngs::OsRng.fill_bytes(&mut key.0);
+        key
+    }
+
+    pub fn as_bytes(&self) -> &[u8; N] {
+        &self.0
+    }
+}
+
+impl<const N: usize> Drop for Key<N> {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl<const N: usize> fmt::Debug for Key<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Key<{}>(<redacted>)", N)
+    }
+}
+
+/// An expanded AES key schedule, zeroed when dropped and never printed by `Debug`.
+///
+/// Dereferences to the `4 * (Nr + 1)` round key words that are in use.
+#[derive(Clone)]
+pub struct KeySchedule {
+    pub(crate) words: [u32; 60],
+    pub(crate) rounds: usize,
+}
+
+impl KeySchedule {
+    /// The number of rounds (Nr) the schedule covers.
+    pub fn rounds(&self) -> usize {
+        self.rounds
+    }
+}
+
+impl Deref for KeySchedule {
+    type Target = [u32];
+
+    fn deref(&self) -> &[u32] {
+        &self.words[..4 * (self.rounds + 1)]
+    }
+}
+
+impl Drop for KeySchedule {
+    fn drop(&mut self) {
+        self.words.zeroize();
+    }
+}
+
+impl fmt::Debug for KeySchedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "KeySchedule {{ rounds: {}, words: <redacted> }}",
+            self.rounds
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::shared::key_expansion;
+
+    #[test]
+    fn test_debug_is_redacted() {
+        let key = Key256::new([0xab; 32]);
+        assert_eq!(format!("{:?}", key), "Key<32>(<redacted>)");
+
+        let schedule = key_expansion(key.as_bytes());
+        let printed = format!("{:?}", schedule);
+        assert_eq!(printed, "KeySchedule { rounds: 14, words: <redacted> }");
+        assert!(!printed.contains("ab"));
+    }
+
+    #[test]
+    fn test_from_slice_checks_length() {
+        let key = Key128::from_slice(&[7; 16]).unwrap();
+        assert_eq!(key.as_bytes(), &[7; 16]);
+
+        assert!(matches!(
+            Key128::from_slice(&[7; 15]),
+            Err(Error::InvalidKeyLength(15))
+        ));
+        assert!(matches!(
+            Key256::from_slice(&[7; 33]),
+            Err(Error::InvalidKeyLength(33))
+        ));
+    }
+
+    #[test]
+    fn test_generate_is_random() {
+        assert_ne!(Key256::generate().as_bytes(), Key256::generate().as_bytes());
+    }
+
+    #[test]
+    fn test_drop_wipes_memory() {
+        let mut key = std::mem::ManuallyDrop::new(Key256::new([0xab; 32]));
+        let mut schedule = std::mem::ManuallyDrop::new(key_expansion(key.as_bytes()));
+
+        // Run the destructors in place so the wiped memory can still be inspected.
+        unsafe {
+            std::ptr::drop_in_place(&mut *key);
+            std::ptr::drop_in_place(&mut *schedule);
+        }
+        assert_eq!(key.0, [0; 32]);
+        assert_eq!(schedule.words, [0; 60]);
+    }
+}
@@ -1,74 +1,104 @@
+#[cfg(target_arch = "x86_64")]
+mod aesni;
+#[cfg(feature = "tokio")]
+pub mod async_stream;
+mod bitslice;
+pub mod cbc;
+pub mod ccm;
+pub mod cfb;
+pub mod cipher;
 mod constant;
+pub mod ctr;
+pub mod cts;
 mod decrypt;
 mod encrypt;
+mod error;
+pub mod gcm;
+pub mod gcm_siv;
+mod io_util;
+mod key;
+pub mod ofb;
+pub mod parallel;
+mod sbox;
+pub mod siv;
+pub mod stream;
+#[cfg(test)]
+mod test_util;
+pub mod trace;
+pub mod ttable;
+pub mod xts;
+
+pub use cipher::{Aes128, Aes192, Aes256, BlockCipher};
+pub use error::{Error, Result};
+pub use key::{Key, Key128, Key192, Key256, KeySchedule};
 
 pub mod shared {
-    use std::io::Cursor;
-
-    use crate::constant::INVERSE_SBOX;
-    use crate::constant::{ROUND_CONSTANTS, SBOX};
+    use crate::constant::ROUND_CONSTANTS;
+    use crate::key::KeySchedule;
+    use crate::sbox::{inverse_substitute_16, substitute_16};
     pub use crate::{decrypt::decrypt_stream, encrypt::encrypt_stream};
 
-    pub fn key_expansion(key: [u8; 32]) -> [u32; 60] {
-        const NK: usize = 8;
-        const NR: usize = 14;
+    /// The number of rounds (Nr) for a key of the given length in bytes.
+    ///
+    /// Panics unless the key is 16, 24 or 32 bytes long. Only the crate calls this, with keys
+    /// whose length [`Key`](crate::Key) has already fixed, so the panic marks a bug rather than
+    /// bad input.
+    pub(crate) fn rounds_for_key_len(key_len: usize) -> usize {
+        match key_len {
+            16 => 10,
+            24 => 12,
+            32 => 14,
+            _ => panic!("AES keys must be 16, 24 or 32 bytes, got {}", key_len),
+        }
+    }
+
+    /// Expands a 128, 192 or 256-bit key. The schedule is built in place so the round keys are
+    /// never left behind in a temporary.
+    pub(crate) fn key_expansion(key: &[u8]) -> KeySchedule {
+        let nr = rounds_for_key_len(key.len());
+        let nk = key.len() / 4;
 
-        let mut w: [u32; 60] = [0; 60];
+        let mut schedule = KeySchedule {
+            words: [0; 60],
+            rounds: nr,
+        };
+        let w = &mut schedule.words;
         let mut temp;
         let mut i = 0;
 
-        while i <= NK - 1 {
+        while i < nk {
             w[i] = u8s_to_u32([key[4 * i], key[4 * i + 1], key[4 * i + 2], key[4 * i + 3]]);
             i += 1;
         }
 
-        assert!(i == NK);
+        assert!(i == nk);
 
-        while i <= 4 * NR + 3 {
+        while i <= 4 * nr + 3 {
             temp = w[i - 1];
-            if i % NK == 0 {
-                temp = sub_word(rot_word(temp)) ^ ROUND_CONSTANTS[(i / NK) - 1]
-            } else if NK > 6 && i % NK == 4 {
+            if i % nk == 0 {
+                temp = sub_word(rot_word(temp)) ^ ROUND_CONSTANTS[(i / nk) - 1]
+            } else if nk > 6 && i % nk == 4 {
                 temp = sub_word(temp)
             }
 
-            w[i] = w[i - NK] ^ temp;
+            w[i] = w[i - nk] ^ temp;
             i += 1;
         }
 
-        w
+        schedule
     }
 
-    pub fn inverse_key_expansion(key: [u8; 32]) -> [u32; 60] {
-        const NK: usize = 8;
-        const NR: usize = 14;
-
-        let mut w: [u32; 60] = [0; 60];
-        let mut dw: [u32; 60] = [0; 60];
-        let mut temp;
-        let mut i = 0;
-
-        while i <= NK - 1 {
-            w[i] = u8s_to_u32([key[4 * i], key[4 * i + 1], key[4 * i + 2], key[4 * i + 3]]);
-            dw[i] = w[i];
-            i += 1;
-        }
+    /// Expands the key schedule for the equivalent inverse cipher.
+    pub(crate) fn inverse_key_expansion(key: &[u8]) -> KeySchedule {
+        inverse_schedule(key_expansion(key))
+    }
 
-        assert!(i == NK);
+    /// Turns a forward key schedule into the one for the equivalent inverse cipher.
+    pub(crate) fn inverse_schedule(mut schedule: KeySchedule) -> KeySchedule {
+        let dw = &mut schedule.words;
 
-        while i <= 4 * NR + 3 {
-            temp = w[i - 1];
-            if i % NK == 0 {
-                temp = sub_word(rot_word(temp)) ^ ROUND_CONSTANTS[(i / NK) - 1];
-            } else if NK > 6 && i % NK == 4 {
-                temp = sub_word(temp);
-            }
-            w[i] = w[i - NK] ^ temp;
-            dw[i] = w[i];
-            i += 1;
-        }
-        for round in 1..=NR - 1 {
-            i = 4 * round;
+        for round in 1..schedule.rounds {
+            let i = 4 * round;
             let mut t1 =
                 expand_block_to_state(round_key_to_block([dw[i], dw[i + 1], dw[i + 2], dw[i + 3]]));
             inverse_mix_columns(&mut t1);
@@ -78,7 +108,7 @@ pub mod shared {
             dw[i + 2] = t2[2];
             dw[i + 3] = t2[3];
         }
-        dw
+        schedule
     }
 
     fn u8s_to_u32(bytes: [u8; 4]) -> u32 {
@@ -89,35 +119,24 @@ pub mod shared {
     }
 
     fn sub_word(word: u32) -> u32 {
-        let b3 = (word >> 24) as u8;
-        let b2 = (word >> 16 & 0xFF) as u8;
-        let b1 = (word >> 8 & 0xFF) as u8;
-        let b0 = (word & 0xFF) as u8;
-
-        let s3 = sub_bytes(b3, SBOX) as u32;
-        let s2 = sub_bytes(b2, SBOX) as u32;
-        let s1 = sub_bytes(b1, SBOX) as u32;
-        let s0 = sub_bytes(b0, SBOX) as u32;
-
-        s3 << 24 | s2 << 16 | s1 << 8 | s0
+        let mut bytes = [0; 16];
+        bytes[..4].copy_from_slice(&word.to_be_bytes());
+        substitute_16(&mut bytes);
+        u32::from_be_bytes(bytes[..4].try_into().unwrap())
     }
 
     pub fn sub_bytes_state(state: &mut [[u8; 4]; 4]) {
-        for i in 0..4 {
-            for j in 0..4 {
-                state[i][j] = sub_bytes(state[i][j], SBOX);
-            }
-        }
+        substitute_16(state.as_flattened_mut().try_into().unwrap());
     }
 
     pub fn inverse_sub_bytes(state: &mut [[u8; 4]; 4]) {
-        for i in 0..4 {
-            for j in 0..4 {
-                state[i][j] = sub_bytes(state[i][j], INVERSE_SBOX);
-            }
-        }
+        inverse_substitute_16(state.as_flattened_mut().try_into().unwrap());
     }
 
+    /// Looks `input` up in a 16x16 substitution table.
+    ///
+    /// The cipher itself does not use this: indexing a table with secret data leaks the index
+    /// through cache timing. See the `sbox` module for the substitution the cipher uses.
     pub fn sub_bytes(input: u8, s_box: [[u8; 16]; 16]) -> u8 {
         let x = input >> 4;
         let y = input & 0xF;
@@ -176,17 +195,14 @@ pub mod shared {
         output
     }
 
-    fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    /// Multiplies in GF(2^8). Always runs eight iterations and selects with masks rather than
+    /// branches, so the time taken does not depend on either operand.
+    pub(crate) fn gf_mul(mut a: u8, mut b: u8) -> u8 {
         let mut result = 0;
-        while b > 0 {
-            if b & 1 != 0 {
-                result ^= a;
-            }
-            let high_bit_set = a & 0x80 != 0;
-            a <<= 1;
-            if high_bit_set {
-                a ^= 0x1B; // XOR with irreducible polynomial x^8 + x^4 + x^3 + x + 1
-            }
+        for _ in 0..8 {
+            result ^= a & (b & 1).wrapping_neg();
+            let high_bit = (a >> 7).wrapping_neg();
+            a = (a << 1) ^ (0x1B & high_bit); // XOR with irreducible polynomial x^8 + x^4 + x^3 + x + 1
             b >>= 1;
         }
         result
@@ -242,6 +258,13 @@ pub mod shared {
         state[3][3] = temp33;
     }
 
+    /// XORs `other` into `block`, for the modes that chain or mask whole blocks.
+    pub(crate) fn xor_block(block: &mut [u8; 16], other: &[u8; 16]) {
+        for (a, b) in block.iter_mut().zip(other) {
+            *a ^= b;
+        }
+    }
+
     pub fn add_round_key(state: [[u8; 4]; 4], round_key: [u32; 4]) -> [[u8; 4]; 4] {
         let mut state_block = flatten_state_to_block(state);
         let key_block = round_key_to_block(round_key);
@@ -308,11 +331,13 @@ pub mod shared {
 
     #[cfg(test)]
     mod test {
+        use std::io::Cursor;
+
+        use crate::cipher::Aes256;
+        use crate::constant::SBOX;
+        use crate::key::Key;
         use crate::shared::*;
-        use crate::{
-            decrypt::{self, decrypt_block, decrypt_stream},
-            encrypt::encrypt_block,
-        };
+        use crate::test_util::ChunkedReader;
 
         #[test]
         fn test_add_round_key() {
@@ -347,7 +372,7 @@ pub mod shared {
                 0xEE, 0xFF,
             ];
 
-            let round_keys = key_expansion(key);
+            let round_keys = key_expansion(&key);
             let mut state = expand_block_to_state(data);
 
             // Initial AddRoundKey
@@ -374,7 +399,7 @@ pub mod shared {
                 0x77, 0x81, 0x1f, 0x35, 0x2c, 0x07, 0x3b, 0x61, 0x08, 0xd7, 0x2d, 0x98, 0x10, 0xa3,
                 0x09, 0x14, 0xdf, 0xf4,
             ];
-            let expanded_keys = key_expansion(test_key);
+            let expanded_keys = key_expansion(&test_key);
 
             let expected_output = [
                 0x603deb10, 0x15ca71be, 0x2b73aef0, 0x857d7781, 0x1f352c07, 0x3b6108d7, 0x2d9810a3,
@@ -388,7 +413,48 @@ pub mod shared {
                 0xfe4890d1, 0xe6188d0b, 0x046df344, 0x706c631e,
             ];
 
-            assert_eq!(expanded_keys, expected_output);
+            assert_eq!(*expanded_keys, expected_output);
+        }
+
+        #[test]
+        fn test_key_expansion_128() {
+            let test_key = [
+                0x2b, 0x7e, 0x15, 0x16, 0x28, 0xae, 0xd2, 0xa6, 0xab, 0xf7, 0x15, 0x88, 0x09, 0xcf,
+                0x4f, 0x3c,
+            ];
+            let expanded_keys = key_expansion(&test_key);
+
+            assert_eq!(
+                expanded_keys[4..8],
+                [0xa0fafe17, 0x88542cb1, 0x23a33939, 0x2a6c7605]
+            );
+            assert_eq!(
+                expanded_keys[40..44],
+                [0xd014f9a8, 0xc9ee2589, 0xe13f0cc8, 0xb6630ca6]
+            );
+            assert_eq!(expanded_keys.len(), 44);
+        }
+
+        #[test]
+        fn test_key_expansion_192() {
+            let test_key = [
+                0x8e, 0x73, 0xb0, 0xf7, 0xda, 0x0e, 0x64, 0x52, 0xc8, 0x10, 0xf3, 0x2b, 0x80, 0x90,
+                0x79, 0xe5, 0x62, 0xf8, 0xea, 0xd2, 0x52, 0x2c, 0x6b, 0x7b,
+            ];
+            let expanded_keys = key_expansion(&test_key);
+
+            assert_eq!(expanded_keys[6..8], [0xfe0c91f7, 0x2402f5a5]);
+            assert_eq!(
+                expanded_keys[48..52],
+                [0xe98ba06f, 0x448c773c, 0x8ecc7204, 0x01002202]
+            );
+            assert_eq!(expanded_keys.len(), 52);
+        }
+
+        #[test]
+        #[should_panic]
+        fn test_key_expansion_rejects_bad_length() {
+            key_expansion(&[0; 20]);
         }
 
         #[test]
@@ -555,18 +621,19 @@ pub mod shared {
             ];
 
             // Encrypt and compare
-            let encrypted_data = encrypt_block(&plaintext, &key);
-            let decrypted_data = decrypt_block(&encrypted_data, &key);
+            let cipher = Aes256::new(&Key::new(key));
+            let encrypted_data = cipher.encrypt_block(&plaintext);
+            let decrypted_data = cipher.decrypt_block(&encrypted_data);
             assert_eq!(decrypted_data, plaintext);
         }
 
         #[test]
         fn test_stream_encrypt_one_block() {
-            let key: [u8; 32] = [
+            let key = Key::new([
                 0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x0B, 0x0C, 0x0D,
                 0x0E, 0x0F, 0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18, 0x19, 0x1A, 0x1B,
                 0x1C, 0x1D, 0x1E, 0x1F,
-            ];
+            ]);
             let plaintext: [u8; 16] = [
                 0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xAA, 0xBB, 0xCC, 0xDD,
                 0xEE, 0xFF,
@@ -602,5 +669,91 @@ pub mod shared {
                 "Decrypted data does not match the original plaintext"
             );
         }
+
+        #[test]
+        fn test_stream_round_trip_unaligned() {
+            let key = Key::new([0x24; 32]);
+
+            for len in [16, 17, 31, 32, 33, 47, 100, 1000] {
+                let plaintext: Vec<u8> = (0..len).map(|i| (i * 13) as u8).collect();
+
+                let mut encrypted = Vec::new();
+                encrypt_stream(Cursor::new(&plaintext), &mut encrypted, &key)
+                    .expect("Encryption failed");
+                assert_eq!(encrypted.len(), plaintext.len());
+
+                let mut decrypted = Vec::new();
+                decrypt_stream(Cursor::new(&encrypted), &mut decrypted, &key)
+                    .expect("Decryption failed");
+                assert_eq!(decrypted, plaintext, "Round trip failed for length {}", len);
+            }
+        }
+
+        #[test]
+        fn test_stream_aligned_matches_ecb() {
+            let key = Key::new([0x24; 32]);
+            let cipher = Aes256::new(&key);
+            let plaintext: Vec<u8> = (0..48).collect();
+
+            let mut encrypted = Vec::new();
+            encrypt_stream(Cursor::new(&plaintext), &mut encrypted, &key)
+                .expect("Encryption failed");
+
+            for (block, encrypted_block) in plaintext.chunks(16).zip(encrypted.chunks(16)) {
+                assert_eq!(
+                    cipher.encrypt_block(block.try_into().unwrap()),
+                    encrypted_block
+                );
+            }
+        }
+
+        #[test]
+        fn test_stream_short_reads() {
+            let key = Key::new([0x24; 32]);
+            let plaintext: Vec<u8> = (0..500).map(|i| (i * 31) as u8).collect();
+
+            let mut expected = Vec::new();
+            encrypt_stream(Cursor::new(&plaintext), &mut expected, &key)
+                .expect("Encryption failed");
+
+            let readers = |data: &[u8]| {
+                vec![
+                    ChunkedReader::fixed(data, 1),
+                    ChunkedReader::fixed(data, 7),
+                    ChunkedReader::random(data, 1),
+                    ChunkedReader::random(data, 2).interrupting(),
+                ]
+            };
+
+            for reader in readers(&plaintext) {
+                let mut encrypted = Vec::new();
+                encrypt_stream(reader, &mut encrypted, &key).expect("Encryption failed");
+                assert_eq!(encrypted, expected, "Short reads changed the ciphertext");
+            }
+
+            for reader in readers(&expected) {
+                let mut decrypted = Vec::new();
+                decrypt_stream(reader, &mut decrypted, &key).expect("Decryption failed");
+                assert_eq!(decrypted, plaintext, "Short reads changed the plaintext");
+            }
+        }
+
+        #[test]
+        fn test_stream_rejects_input_shorter_than_a_block() {
+            let key = Key::new([0x24; 32]);
+            let mut encrypted = Vec::new();
+
+            assert!(matches!(
+                encrypt_stream(Cursor::new(&[1u8; 15]), &mut encrypted, &key),
+                Err(crate::Error::InputTooShort(15))
+            ));
+            assert!(matches!(
+                decrypt_stream(Cursor::new(&[1u8; 15]), &mut encrypted, &key),
+                Err(crate::Error::TruncatedInput)
+            ));
+
+            encrypt_stream(Cursor::new(&[]), &mut encrypted, &key).expect("Encryption failed");
+            assert!(encrypted.is_empty());
+        }
     }
 }
@@ -0,0 +1,128 @@
+// This file holds the output feedback (OFB) mode from NIST SP 800-38A, which turns the forward
+// block cipher into a synchronous stream cipher.
+
+use std::io::{Read, Write};
+
+use crate::cipher::BlockCipher;
+use crate::error::Result;
+use crate::io_util::process_stream;
+
+const BLOCK_SIZE: usize = 16;
+
+/// An OFB keystream generator. Encryption and decryption are the same operation.
+///
+/// Each keystream block is the encryption of the one before it, starting from the IV, so the
+/// keystream does not depend on the data. An IV must never be reused with the same key.
+pub struct Ofb<C> {
+    cipher: C,
+    keystream: [u8; BLOCK_SIZE],
+    used: usize,
+}
+
+impl<C: BlockCipher> Ofb<C> {
+    pub fn new(cipher: C, iv: &[u8; BLOCK_SIZE]) -> Self {
+        Ofb {
+            cipher,
+            keystream: *iv,
+            used: BLOCK_SIZE,
+        }
+    }
+
+    /// XORs the keystream into `data`. The data may be split at any point between calls.
+    pub fn apply_keystream(&mut self, data: &mut [u8]) {
+        for byte in data.iter_mut() {
+            if self.used == BLOCK_SIZE {
+                self.keystream = self.cipher.encrypt_block(&self.keystream);
+                self.used = 0;
+            }
+            *byte ^= self.keystream[self.used];
+            self.used += 1;
+        }
+    }
+}
+
+/// Encrypts or decrypts the input stream into the output stream. The output is exactly as long as
+/// the input.
+pub fn apply_stream<R: Read, W: Write, C: BlockCipher>(
+    reader: R,
+    writer: W,
+    cipher: C,
+    iv: &[u8; BLOCK_SIZE],
+) -> Result<()> {
+    let mut ofb = Ofb::new(cipher, iv);
+    process_stream(reader, writer, |data| ofb.apply_keystream(data))
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::cipher::{Aes128, Aes256};
+    use crate::key::Key;
+    use crate::test_util::{hex, hex_array, ChunkedReader};
+
+    const SP800_38A_PLAINTEXT: &str = "
+        6bc1bee22e409f96e93d7e117393172a ae2d8a571e03ac9c9eb76fac45af8e51
+        30c81c46a35ce411e5fbc1191a0a52ef f69f2445df4f9b17ad2b417be66c3710";
+
+    #[test]
+    fn test_sp800_38a_ofb_aes128() {
+        let cipher = Aes128::new(&Key::new(hex_array("2b7e151628aed2a6abf7158809cf4f3c")));
+        let iv = hex_array("000102030405060708090a0b0c0d0e0f");
+        let ciphertext = hex(
+            "3b3fd92eb72dad20333449f8e83cfb4a 7789508d16918f03f53c52dac54ed825
+             9740051e9c5fecf64344f7a82260edcc 304c6528f659c77866a510d9c1d6ae5e",
+        );
+
+        let mut data = hex(SP800_38A_PLAINTEXT);
+        Ofb::new(&cipher, &iv).apply_keystream(&mut data);
+        assert_eq!(data, ciphertext);
+
+        Ofb::new(&cipher, &iv).apply_keystream(&mut data);
+        assert_eq!(data, hex(SP800_38A_PLAINTEXT));
+    }
+
+    #[test]
+    fn test_sp800_38a_ofb_aes256() {
+        let cipher = Aes256::new(&Key::new(hex_array(
+            "603deb1015ca71be2b73aef0857d77811f352c073b6108d72d9810a30914dff4",
+        )));
+        let iv = hex_array("000102030405060708090a0b0c0d0e0f");
+        let ciphertext = hex(
+            "dc7e84bfda79164b7ecd8486985d3860 4febdc6740d20b3ac88f6ad82a4fb08d
+             71ab47a086e86eedf39d1c5bba97c408 0126141d67f37be8538f5a8be740e484",
+        );
+
+        // Feed the data through in uneven pieces to exercise partial keystream blocks.
+        let mut data = hex(SP800_38A_PLAINTEXT);
+        let mut ofb = Ofb::new(&cipher, &iv);
+        for piece in data.chunks_mut(7) {
+            ofb.apply_keystream(piece);
+        }
+        assert_eq!(data, ciphertext);
+    }
+
+    #[test]
+    fn test_apply_stream() {
+        let cipher = Aes256::new(&Key::new([5; 32]));
+        let iv = [0xab; 16];
+
+        for len in [0, 1, 15, 16, 17, 1000, 5000] {
+            let plaintext: Vec<u8> = (0..len).map(|i| (i * 7) as u8).collect();
+
+            let mut encrypted = Vec::new();
+            apply_stream(Cursor::new(&plaintext), &mut encrypted, &cipher, &iv).unwrap();
+            assert_eq!(encrypted.len(), plaintext.len());
+
+            let mut decrypted = Vec::new();
+            apply_stream(Cursor::new(&encrypted), &mut decrypted, &cipher, &iv).unwrap();
+            assert_eq!(decrypted, plaintext);
+
+            let mut chunked = Vec::new();
+            let reader = ChunkedReader::random(&plaintext, len as u64).interrupting();
+            apply_stream(reader, &mut chunked, &cipher, &iv).unwrap();
+            assert_eq!(chunked, encrypted);
+        }
+    }
+}
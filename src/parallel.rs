@@ -0,0 +1,661 @@
+// This file holds the multi-threaded file encryption for the modes whose chunks can be processed
+// independently of each other.
+//
+// The input is split into fixed-size chunks that worker threads claim one at a time, each reading
+// its chunk at the chunk's offset and writing the result at the matching offset in the output.
+// Every chunk is processed exactly as the single-threaded path would process it, so the output is
+// byte-identical whatever the number of threads. GCM cannot be split this way, because its tag
+// covers the whole message, so it is applied to each chunk separately with its own nonce and tag.
+//
+// The workers write into a temporary file next to the output, which is only renamed into place
+// once every chunk has succeeded. A failure never leaves a partial output behind or replaces an
+// existing file, and the first failure stops the other workers from claiming further chunks.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+
+use crate::cipher::Aes256;
+use crate::ctr::Ctr;
+use crate::cts::{self, Variant};
+use crate::error::{Error, Result};
+use crate::gcm::Gcm;
+use crate::key::Key256;
+use crate::xts::{self, Xts};
+
+const BLOCK_SIZE: usize = 16;
+
+/// The amount of data a worker claims at a time. Each worker holds one chunk in memory.
+const CHUNK_SIZE: usize = 1024 * 1024;
+
+/// The length of the tag after each chunk in [`Mode::Gcm`].
+const TAG_LEN: usize = 16;
+
+/// A mode of operation that can be split across threads.
+#[derive(Clone, Debug)]
+pub enum Mode {
+    /// ECB with ciphertext stealing, as produced by
+    /// [`shared::encrypt_stream`](crate::shared::encrypt_stream).
+    Ecb,
+    /// CTR from the given initial counter block, as produced by
+    /// [`ctr::apply_stream`](crate::ctr::apply_stream).
+    Ctr([u8; BLOCK_SIZE]),
+    /// GCM over 1 MiB chunks, each sealed with [`Gcm::seal`] without AAD and followed by its
+    /// 16-byte tag. The nonce for a chunk is the given 7-byte prefix, the chunk index as a
+    /// big-endian `u32`, then 1 for the last chunk and 0 otherwise, so chunks cannot be
+    /// reordered, dropped or truncated at a chunk boundary without failing authentication. An
+    /// empty file is sealed as a single empty chunk. A prefix must never be reused with the
+    /// same key.
+    Gcm([u8; 7]),
+    /// XTS with the key passed to the function as the data key, numbering sectors of
+    /// `sector_size` bytes from zero, as produced by [`Xts::encrypt_sectors`].
+    Xts {
+        tweak_key: Key256,
+        sector_size: usize,
+    },
+}
+
+#[derive(Clone, Copy)]
+enum Direction {
+    Encrypt,
+    Decrypt,
+}
+
+/// How the file is divided between the workers.
+struct Layout {
+    /// The number of chunks the workers process.
+    chunks: u64,
+    /// The length of every chunk but the last in the input.
+    input_chunk: u64,
+    /// The length of every chunk but the last in the output.
+    output_chunk: u64,
+    /// The length of the input covered by the chunks.
+    bulk_len: u64,
+    output_len: u64,
+}
+
+impl Layout {
+    /// Chunks that are the same length in the input and the output.
+    fn in_place(bulk_len: u64, chunk: u64, output_len: u64) -> Self {
+        Layout {
+            chunks: bulk_len.div_ceil(chunk),
+            input_chunk: chunk,
+            output_chunk: chunk,
+            bulk_len,
+            output_len,
+        }
+    }
+}
+
+/// Encrypts the file at `input` into `output` using up to `threads` worker threads.
+///
+/// The output is byte-identical to the single-threaded function for the same mode. A `threads`
+/// of zero is treated as one. `input` and `output` must be different files.
+pub fn par_encrypt_file(
+    input: &Path,
+    output: &Path,
+    key: &Key256,
+    mode: &Mode,
+    threads: usize,
+) -> Result<()> {
+    process_file(input, output, key, mode, threads, Direction::Encrypt)
+}
+
+/// Decrypts a file produced by [`par_encrypt_file`], or by the single-threaded function for the
+/// same mode, using up to `threads` worker threads.
+pub fn par_decrypt_file(
+    input: &Path,
+    output: &Path,
+    key: &Key256,
+    mode: &Mode,
+    threads: usize,
+) -> Result<()> {
+    process_file(input, output, key, mode, threads, Direction::Decrypt)
+}
+
+fn process_file(
+    input: &Path,
+    output: &Path,
+    key: &Key256,
+    mode: &Mode,
+    threads: usize,
+    direction: Direction,
+) -> Result<()> {
+    if same_file(input, output)? {
+        return Err(Error::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "input and output are the same file",
+        )));
+    }
+
+    let cipher = Aes256::new(key);
+    let len = fs::metadata(input)?.len();
+    let chunk = CHUNK_SIZE as u64;
+
+    let layout = match (mode, direction) {
+        (Mode::Ecb, _) => Layout::in_place(ecb_bulk_len(len), chunk, len),
+        (Mode::Ctr(_), _) => Layout::in_place(len, chunk, len),
+        (Mode::Xts { sector_size, .. }, _) => {
+            xts::check_sectors(0, *sector_size, 0)?;
+            if let Some(short) = xts::short_final_sector(*sector_size as u64, len) {
+                return Err(match direction {
+                    Direction::Encrypt => Error::InputTooShort(short),
+                    Direction::Decrypt => Error::TruncatedInput,
+                });
+            }
+            let sectors_per_chunk = (CHUNK_SIZE / sector_size).max(1);
+            Layout::in_place(len, (sectors_per_chunk * sector_size) as u64, len)
+        }
+        (Mode::Gcm(_), Direction::Encrypt) => {
+            let chunks = len.div_ceil(chunk).max(1);
+            if chunks > 1 << 32 {
+                return Err(Error::InputTooLong(
+                    usize::try_from(len).unwrap_or(usize::MAX),
+                ));
+            }
+            Layout {
+                chunks,
+                input_chunk: chunk,
+                output_chunk: chunk + TAG_LEN as u64,
+                bulk_len: len,
+                output_len: len + chunks * TAG_LEN as u64,
+            }
+        }
+        (Mode::Gcm(_), Direction::Decrypt) => {
+            let sealed_chunk = chunk + TAG_LEN as u64;
+            let chunks = len.div_ceil(sealed_chunk);
+            if chunks == 0 {
+                return Err(Error::TruncatedInput);
+            }
+            Layout {
+                chunks,
+                input_chunk: sealed_chunk,
+                output_chunk: chunk,
+                bulk_len: len,
+                output_len: len.saturating_sub(chunks * TAG_LEN as u64),
+            }
+        }
+    };
+
+    // The final blocks of an ECB file may be joined by ciphertext stealing, so they are handled
+    // separately once the rest has been split across the workers. The tail is processed first so
+    // that short input is rejected before the output file is created.
+    let mut tail = Vec::new();
+    if layout.bulk_len < len {
+        let mut file = File::open(input)?;
+        file.seek(SeekFrom::Start(layout.bulk_len))?;
+        file.read_to_end(&mut tail)?;
+        match direction {
+            Direction::Encrypt => cts::ecb_encrypt(&cipher, &mut tail, Variant::Cs2)?,
+            Direction::Decrypt => cts::ecb_decrypt(&cipher, &mut tail, Variant::Cs2)?,
+        }
+    }
+
+    let xts = match mode {
+        Mode::Xts { tweak_key, .. } => Some(Xts::from_keys(key, tweak_key)?),
+        _ => None,
+    };
+    let gcm = Gcm::new(&cipher);
+
+    let (pending, mut file) = PendingOutput::create(output)?;
+    file.set_len(layout.output_len)?;
+
+    let process = |index: u64, chunk: &mut Vec<u8>| -> Result<()> {
+        match mode {
+            Mode::Ecb => {
+                let blocks = chunk.as_chunks_mut().0;
+                match direction {
+                    Direction::Encrypt => cipher.encrypt_blocks(blocks),
+                    Direction::Decrypt => cipher.decrypt_blocks(blocks),
+                }
+            }
+            // Encryption and decryption are the same operation in CTR mode.
+            Mode::Ctr(initial_counter) => {
+                let mut ctr = Ctr::new(&cipher, initial_counter);
+                ctr.seek(index * layout.input_chunk);
+                ctr.apply_keystream(chunk);
+            }
+            Mode::Gcm(prefix) => {
+                let nonce = chunk_nonce(prefix, index, index + 1 == layout.chunks);
+                *chunk = match direction {
+                    Direction::Encrypt => gcm.seal(&nonce, &[], chunk)?,
+                    Direction::Decrypt => gcm.open(&nonce, &[], chunk)?,
+                };
+            }
+            Mode::Xts { sector_size, .. } => {
+                let xts = xts.as_ref().unwrap();
+                let first_sector = (index * layout.input_chunk / *sector_size as u64) as u128;
+                match direction {
+                    Direction::Encrypt => xts.encrypt_sectors(first_sector, *sector_size, chunk)?,
+                    Direction::Decrypt => xts.decrypt_sectors(first_sector, *sector_size, chunk)?,
+                }
+            }
+        }
+        Ok(())
+    };
+
+    let next_index = AtomicU64::new(0);
+    let failed = AtomicBool::new(false);
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads.max(1))
+            .map(|_| {
+                scope.spawn(|| {
+                    let result = work(
+                        input,
+                        &pending.path,
+                        &layout,
+                        &next_index,
+                        &failed,
+                        &process,
+                    );
+                    if result.is_err() {
+                        failed.store(true, Ordering::Relaxed);
+                    }
+                    result
+                })
+            })
+            .collect();
+
+        workers.into_iter().try_for_each(|worker| {
+            worker
+                .join()
+                .unwrap_or_else(|_| Err(Error::Io(io::Error::other("worker thread panicked"))))
+        })
+    })?;
+
+    file.seek(SeekFrom::Start(layout.bulk_len))?;
+    file.write_all(&tail)?;
+    file.sync_all()?;
+
+    pending.persist()
+}
+
+/// The nonce for chunk `index` of a [`Mode::Gcm`] file.
+fn chunk_nonce(prefix: &[u8; 7], index: u64, last: bool) -> [u8; 12] {
+    let mut nonce = [0; 12];
+    nonce[..7].copy_from_slice(prefix);
+    nonce[7..11].copy_from_slice(&(index as u32).to_be_bytes());
+    nonce[11] = u8::from(last);
+    nonce
+}
+
+/// Whether both paths name the same existing file, following symbolic links.
+fn same_file(input: &Path, output: &Path) -> Result<bool> {
+    match fs::canonicalize(output) {
+        Ok(output) => Ok(fs::canonicalize(input)? == output),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// A temporary file in the output's directory that is removed when dropped, unless it has been
+/// renamed over the output.
+struct PendingOutput<'a> {
+    output: &'a Path,
+    path: PathBuf,
+    persisted: bool,
+}
+
+/// Numbers the temporary files within this process, so concurrent jobs writing the same output
+/// never share one.
+static NEXT_TEMP_ID: AtomicU64 = AtomicU64::new(0);
+
+impl<'a> PendingOutput<'a> {
+    /// Creates a new temporary file for `output`. The file is created exclusively, so a name
+    /// left behind by another process is skipped rather than reused.
+    fn create(output: &'a Path) -> Result<(Self, File)> {
+        let file_name = output.file_name().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "output path has no file name")
+        })?;
+
+        loop {
+            let mut temp_name = std::ffi::OsString::from(".");
+            temp_name.push(file_name);
+            temp_name.push(format!(
+                ".raes-{}-{}.tmp",
+                std::process::id(),
+                NEXT_TEMP_ID.fetch_add(1, Ordering::Relaxed)
+            ));
+            let path = output.with_file_name(temp_name);
+
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => {
+                    let pending = PendingOutput {
+                        output,
+                        path,
+                        persisted: false,
+                    };
+                    return Ok((pending, file));
+                }
+                Err(error) if error.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(error) => return Err(error.into()),
+            }
+        }
+    }
+
+    fn persist(mut self) -> Result<()> {
+        fs::rename(&self.path, self.output)?;
+        self.persisted = true;
+        Ok(())
+    }
+}
+
+impl Drop for PendingOutput<'_> {
+    fn drop(&mut self) {
+        if !self.persisted {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Claims chunks until none are left or another worker has failed, processing each and writing
+/// it to the output.
+fn work(
+    input: &Path,
+    output: &Path,
+    layout: &Layout,
+    next_index: &AtomicU64,
+    failed: &AtomicBool,
+    process: &(impl Fn(u64, &mut Vec<u8>) -> Result<()> + Sync),
+) -> Result<()> {
+    let mut reader = File::open(input)?;
+    let mut writer = OpenOptions::new().write(true).open(output)?;
+    let mut buffer = Vec::with_capacity(layout.input_chunk as usize);
+
+    loop {
+        if failed.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        let index = next_index.fetch_add(1, Ordering::Relaxed);
+        if index >= layout.chunks {
+            return Ok(());
+        }
+
+        let offset = index * layout.input_chunk;
+        let len = (layout.bulk_len - offset).min(layout.input_chunk);
+        buffer.resize(len as usize, 0);
+        reader.seek(SeekFrom::Start(offset))?;
+        reader.read_exact(&mut buffer)?;
+
+        process(index, &mut buffer)?;
+
+        writer.seek(SeekFrom::Start(index * layout.output_chunk))?;
+        writer.write_all(&buffer)?;
+    }
+}
+
+/// The length of the leading whole blocks of an ECB file that are not part of the ciphertext
+/// stealing at the end. Input shorter than a block is left entirely to the stealing, which
+/// rejects it.
+fn ecb_bulk_len(len: u64) -> u64 {
+    let block = BLOCK_SIZE as u64;
+    match len % block {
+        0 => len,
+        partial => len.saturating_sub(partial + block),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::ctr;
+    use crate::error::Error;
+    use crate::key::Key;
+    use crate::shared::{decrypt_stream, encrypt_stream};
+
+    /// A file in the temporary directory that is removed when dropped.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            let file_name = format!("raes-parallel-{}-{}", std::process::id(), name);
+            TempFile(std::env::temp_dir().join(file_name))
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn sample(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 % 251) as u8).collect()
+    }
+
+    /// Runs the parallel functions on `data` with several thread counts and returns the
+    /// ciphertext, checking that it decrypts back to `data` each time.
+    fn round_trip(name: &str, data: &[u8], mode: &Mode) -> Vec<u8> {
+        let key = Key::new([0x24; 32]);
+        let plaintext = TempFile::new(&format!("{}-plain", name));
+        let ciphertext = TempFile::new(&format!("{}-cipher", name));
+        let decrypted = TempFile::new(&format!("{}-decrypted", name));
+        fs::write(&plaintext.0, data).unwrap();
+
+        let mut first = None;
+        for threads in [1, 3, 8] {
+            par_encrypt_file(&plaintext.0, &ciphertext.0, &key, mode, threads).unwrap();
+            let encrypted = fs::read(&ciphertext.0).unwrap();
+            assert_eq!(*first.get_or_insert_with(|| encrypted.clone()), encrypted);
+
+            par_decrypt_file(&ciphertext.0, &decrypted.0, &key, mode, threads).unwrap();
+            assert!(fs::read(&decrypted.0).unwrap() == data);
+        }
+        first.unwrap()
+    }
+
+    #[test]
+    fn test_ecb_matches_single_threaded() {
+        let key = Key::new([0x24; 32]);
+        for len in [0, 16, 40, 3 * CHUNK_SIZE + 37] {
+            let data = sample(len);
+            let parallel = round_trip(&format!("ecb-{}", len), &data, &Mode::Ecb);
+
+            let mut expected = Vec::new();
+            encrypt_stream(&data[..], &mut expected, &key).unwrap();
+            assert!(parallel == expected, "length {}", len);
+
+            let mut decrypted = Vec::new();
+            decrypt_stream(&parallel[..], &mut decrypted, &key).unwrap();
+            assert!(decrypted == data);
+        }
+    }
+
+    #[test]
+    fn test_ctr_matches_single_threaded() {
+        let key = Key::new([0x24; 32]);
+        let counter = [0xf0; 16];
+        for len in [0, 5, 2 * CHUNK_SIZE + 21] {
+            let data = sample(len);
+            let parallel = round_trip(&format!("ctr-{}", len), &data, &Mode::Ctr(counter));
+
+            let mut expected = Vec::new();
+            ctr::apply_stream(&data[..], &mut expected, Aes256::new(&key), &counter).unwrap();
+            assert!(parallel == expected, "length {}", len);
+        }
+    }
+
+    #[test]
+    fn test_gcm_chunks_match_one_shot_seal() {
+        let key = Key::new([0x24; 32]);
+        let gcm = Gcm::new(Aes256::new(&key));
+        let prefix = [7; 7];
+        for len in [0, 5, CHUNK_SIZE, 2 * CHUNK_SIZE + 21] {
+            let data = sample(len);
+            let parallel = round_trip(&format!("gcm-{}", len), &data, &Mode::Gcm(prefix));
+
+            let chunks: Vec<&[u8]> = if data.is_empty() {
+                vec![&[]]
+            } else {
+                data.chunks(CHUNK_SIZE).collect()
+            };
+            let mut expected = Vec::new();
+            for (i, chunk) in chunks.iter().enumerate() {
+                let nonce = chunk_nonce(&prefix, i as u64, i + 1 == chunks.len());
+                expected.extend(gcm.seal(&nonce, &[], chunk).unwrap());
+            }
+            assert!(parallel == expected, "length {}", len);
+        }
+    }
+
+    #[test]
+    fn test_gcm_rejects_modified_files() {
+        let key = Key::new([0x24; 32]);
+        let mode = Mode::Gcm([7; 7]);
+        let plaintext = TempFile::new("gcm-modified-plain");
+        let ciphertext = TempFile::new("gcm-modified-cipher");
+        let output = TempFile::new("gcm-modified-output");
+        fs::write(&plaintext.0, sample(2 * CHUNK_SIZE + 21)).unwrap();
+        par_encrypt_file(&plaintext.0, &ciphertext.0, &key, &mode, 3).unwrap();
+        let sealed = fs::read(&ciphertext.0).unwrap();
+        let sealed_chunk = CHUNK_SIZE + TAG_LEN;
+
+        let mut flipped = sealed.clone();
+        flipped[sealed_chunk + 100] ^= 1;
+        let swapped = [
+            &sealed[sealed_chunk..2 * sealed_chunk],
+            &sealed[..sealed_chunk],
+            &sealed[2 * sealed_chunk..],
+        ]
+        .concat();
+        // Dropping the last chunk leaves a file that ends on a chunk not marked as the last.
+        let truncated = sealed[..2 * sealed_chunk].to_vec();
+
+        for modified in [flipped, swapped, truncated] {
+            fs::write(&ciphertext.0, modified).unwrap();
+            assert!(matches!(
+                par_decrypt_file(&ciphertext.0, &output.0, &key, &mode, 3),
+                Err(Error::AuthenticationFailed)
+            ));
+            assert!(!output.0.exists());
+        }
+
+        for short in [&[][..], &sealed[..15]] {
+            fs::write(&ciphertext.0, short).unwrap();
+            assert!(matches!(
+                par_decrypt_file(&ciphertext.0, &output.0, &key, &mode, 3),
+                Err(Error::TruncatedInput)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_xts_matches_single_threaded() {
+        let key = Key::new([0x24; 32]);
+        let tweak_key = Key::new([0x42; 32]);
+        let xts = Xts::from_keys(&key, &tweak_key).unwrap();
+        for (len, sector_size) in [
+            (0, 512),
+            (3 * CHUNK_SIZE + 37, 4096),
+            (2 * CHUNK_SIZE + 100, 3000),
+            (3 * CHUNK_SIZE, CHUNK_SIZE + 512),
+        ] {
+            let data = sample(len);
+            let mode = Mode::Xts {
+                tweak_key: tweak_key.clone(),
+                sector_size,
+            };
+            let parallel = round_trip(&format!("xts-{}-{}", len, sector_size), &data, &mode);
+
+            let mut expected = data.clone();
+            xts.encrypt_sectors(0, sector_size, &mut expected).unwrap();
+            assert!(parallel == expected, "length {}", len);
+        }
+    }
+
+    #[test]
+    fn test_xts_rejects_bad_parameters() {
+        let key = Key::new([0x24; 32]);
+        let input = TempFile::new("xts-bad-input");
+        let output = TempFile::new("xts-bad-output");
+        fs::write(&input.0, sample(100)).unwrap();
+
+        let mode = Mode::Xts {
+            tweak_key: key.clone(),
+            sector_size: 512,
+        };
+        assert!(matches!(
+            par_encrypt_file(&input.0, &output.0, &key, &mode, 2),
+            Err(Error::WeakKey)
+        ));
+
+        let mode = Mode::Xts {
+            tweak_key: Key::new([0x42; 32]),
+            sector_size: 0,
+        };
+        assert!(matches!(
+            par_encrypt_file(&input.0, &output.0, &key, &mode, 2),
+            Err(Error::InvalidSectorSize(0))
+        ));
+
+        // The last sector of 100 bytes with 96-byte sectors is too short to encrypt.
+        let mode = Mode::Xts {
+            tweak_key: Key::new([0x42; 32]),
+            sector_size: 96,
+        };
+        assert!(matches!(
+            par_encrypt_file(&input.0, &output.0, &key, &mode, 2),
+            Err(Error::InputTooShort(4))
+        ));
+        assert!(!output.0.exists());
+    }
+
+    #[test]
+    fn test_ecb_rejects_short_input() {
+        let key = Key::new([0x24; 32]);
+        let input = TempFile::new("short-input");
+        let output = TempFile::new("short-output");
+        fs::write(&input.0, [1; 15]).unwrap();
+
+        assert!(matches!(
+            par_encrypt_file(&input.0, &output.0, &key, &Mode::Ecb, 2),
+            Err(Error::InputTooShort(15))
+        ));
+        assert!(matches!(
+            par_decrypt_file(&input.0, &output.0, &key, &Mode::Ecb, 2),
+            Err(Error::TruncatedInput)
+        ));
+        assert!(!output.0.exists());
+    }
+
+    #[test]
+    fn test_rejects_same_input_and_output() {
+        let key = Key::new([0x24; 32]);
+        let file = TempFile::new("same-file");
+        fs::write(&file.0, sample(100)).unwrap();
+
+        assert!(matches!(
+            par_encrypt_file(&file.0, &file.0, &key, &Mode::Ecb, 2),
+            Err(Error::Io(e)) if e.kind() == io::ErrorKind::InvalidInput
+        ));
+        assert_eq!(fs::read(&file.0).unwrap(), sample(100));
+    }
+
+    #[test]
+    fn test_failure_keeps_existing_output() {
+        let key = Key::new([0x24; 32]);
+        let input = TempFile::new("keep-input");
+        let output = TempFile::new("keep-output");
+        fs::write(&input.0, [1; 15]).unwrap();
+        fs::write(&output.0, b"previous contents").unwrap();
+
+        assert!(par_encrypt_file(&input.0, &output.0, &key, &Mode::Ecb, 2).is_err());
+        assert_eq!(fs::read(&output.0).unwrap(), b"previous contents");
+    }
+
+    #[test]
+    fn test_pending_outputs_do_not_collide() {
+        let output = TempFile::new("pending-output");
+        let (first, _) = PendingOutput::create(&output.0).unwrap();
+        let (second, _) = PendingOutput::create(&output.0).unwrap();
+        assert_ne!(first.path, second.path);
+
+        let (first_path, second_path) = (first.path.clone(), second.path.clone());
+        drop((first, second));
+        assert!(!first_path.exists() && !second_path.exists());
+    }
+}
@@ -0,0 +1,139 @@
+// This file holds the SubBytes byte substitution used by the cipher and the key schedule.
+//
+// By default the S-box is computed rather than looked up: the bytes are transposed into bit
+// planes and run through the same circuit as the bitsliced backend, which inverts them in GF(2^8)
+// and applies the affine transform from FIPS-197 section 5.1.1. That takes the same sequence of
+// operations for every input, unlike a table lookup, whose memory access pattern leaks the secret
+// index through the cache. All sixteen bytes of a state go through the circuit together, which is
+// what keeps it affordable: one pass costs about as much as inverting a single byte did.
+//
+// It is still slower than the tables. In the reference AES-128 rows of `cargo bench --bench
+// backends --features bench`, the software rounds run at about 6 MiB/s with the computed S-box
+// and about 14 MiB/s with `--features bench,table-sbox`; substituting one byte at a time ran at
+// about 1.1 MiB/s. CPUs with AES-NI use neither. The `table-sbox` feature switches back to the
+// lookup tables, which are easier to follow when stepping through the cipher.
+
+#[cfg(not(feature = "table-sbox"))]
+use crate::bitslice::{self, Planes};
+#[cfg(feature = "table-sbox")]
+use crate::constant::{INVERSE_SBOX, SBOX};
+
+/// Applies the S-box to a single byte. The cipher always substitutes whole states or words, so
+/// this is only used to check the S-box against the tables.
+#[cfg(test)]
+pub fn substitute(byte: u8) -> u8 {
+    let mut bytes = [byte; 16];
+    substitute_16(&mut bytes);
+    bytes[0]
+}
+
+/// Applies the inverse S-box to a single byte.
+#[cfg(test)]
+pub fn inverse_substitute(byte: u8) -> u8 {
+    let mut bytes = [byte; 16];
+    inverse_substitute_16(&mut bytes);
+    bytes[0]
+}
+
+/// Applies the S-box to each of sixteen bytes, such as a cipher state.
+#[cfg(not(feature = "table-sbox"))]
+pub fn substitute_16(bytes: &mut [u8; 16]) {
+    through_planes(bytes, bitslice::sub_bytes);
+}
+
+/// Applies the inverse S-box to each of sixteen bytes.
+#[cfg(not(feature = "table-sbox"))]
+pub fn inverse_substitute_16(bytes: &mut [u8; 16]) {
+    through_planes(bytes, bitslice::inverse_sub_bytes);
+}
+
+/// Applies the S-box to each of sixteen bytes, such as a cipher state.
+#[cfg(feature = "table-sbox")]
+pub fn substitute_16(bytes: &mut [u8; 16]) {
+    for byte in bytes.iter_mut() {
+        *byte = SBOX[(*byte >> 4) as usize][(*byte & 0xF) as usize];
+    }
+}
+
+/// Applies the inverse S-box to each of sixteen bytes.
+#[cfg(feature = "table-sbox")]
+pub fn inverse_substitute_16(bytes: &mut [u8; 16]) {
+    for byte in bytes.iter_mut() {
+        *byte = INVERSE_SBOX[(*byte >> 4) as usize][(*byte & 0xF) as usize];
+    }
+}
+
+/// Transposes the bytes into bit planes, so that plane `b` holds bit `b` of byte `i` at bit `i`,
+/// runs `circuit` over them and transposes the result back.
+#[cfg(not(feature = "table-sbox"))]
+fn through_planes(bytes: &mut [u8; 16], circuit: fn(&mut Planes)) {
+    let low = transpose_8x8(u64::from_le_bytes(bytes[..8].try_into().unwrap()));
+    let high = transpose_8x8(u64::from_le_bytes(bytes[8..].try_into().unwrap()));
+
+    let mut planes = [0; 8];
+    for (b, plane) in planes.iter_mut().enumerate() {
+        *plane = (low >> (8 * b)) & 0xFF | ((high >> (8 * b)) & 0xFF) << 8;
+    }
+    circuit(&mut planes);
+
+    // The circuit complements whole planes for the affine constant, so only the low 16 bits of
+    // each plane are meaningful.
+    let (mut low, mut high) = (0, 0);
+    for (b, plane) in planes.iter().enumerate() {
+        low |= (plane & 0xFF) << (8 * b);
+        high |= ((plane >> 8) & 0xFF) << (8 * b);
+    }
+    bytes[..8].copy_from_slice(&transpose_8x8(low).to_le_bytes());
+    bytes[8..].copy_from_slice(&transpose_8x8(high).to_le_bytes());
+}
+
+/// Transposes an 8x8 bit matrix held with row `i` in byte `i`, so that bit `j` of byte `i` moves
+/// to bit `i` of byte `j`. The transpose is its own inverse.
+#[cfg(not(feature = "table-sbox"))]
+fn transpose_8x8(mut x: u64) -> u64 {
+    let t = (x ^ (x >> 7)) & 0x00AA_00AA_00AA_00AA;
+    x ^= t ^ (t << 7);
+    let t = (x ^ (x >> 14)) & 0x0000_CCCC_0000_CCCC;
+    x ^= t ^ (t << 14);
+    let t = (x ^ (x >> 28)) & 0x0000_0000_F0F0_F0F0;
+    x ^ t ^ (t << 28)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::constant::{INVERSE_SBOX, SBOX};
+
+    #[test]
+    fn test_matches_tables() {
+        for byte in 0..=255u8 {
+            let (row, column) = ((byte >> 4) as usize, (byte & 0xF) as usize);
+            assert_eq!(
+                substitute(byte),
+                SBOX[row][column],
+                "S-box of {:#04x}",
+                byte
+            );
+            assert_eq!(
+                inverse_substitute(byte),
+                INVERSE_SBOX[row][column],
+                "inverse S-box of {:#04x}",
+                byte
+            );
+        }
+    }
+
+    #[test]
+    fn test_substitute_16_matches_single_bytes() {
+        for start in (0..=255u8).step_by(16) {
+            let original: [u8; 16] = std::array::from_fn(|i| start.wrapping_add(i as u8 * 17));
+            let mut bytes = original;
+            substitute_16(&mut bytes);
+            for (byte, input) in bytes.iter().zip(&original) {
+                assert_eq!(*byte, substitute(*input));
+            }
+            inverse_substitute_16(&mut bytes);
+            assert_eq!(bytes, original);
+        }
+    }
+}
@@ -0,0 +1,293 @@
+// This file holds the synthetic initialization vector (SIV) mode from RFC 5297, a deterministic
+// authenticated encryption that stays secure when a nonce is repeated or left out.
+
+use zeroize::Zeroizing;
+
+use crate::cipher::{Aes128, Aes256, BlockCipher};
+use crate::ctr::Ctr;
+use crate::error::{Error, Result};
+use crate::gcm::constant_time_eq;
+use crate::key::{Key, Key256};
+use crate::shared::xor_block;
+
+const BLOCK_SIZE: usize = 16;
+
+/// The most associated data components S2V accepts, leaving one input for the plaintext.
+const MAX_COMPONENTS: usize = 126;
+
+/// SIV over a pair of block ciphers: one for the S2V MAC (K1) and one for CTR encryption (K2).
+///
+/// The synthetic IV is a MAC over the associated data and the plaintext, so sealing the same
+/// inputs twice gives the same output and reveals only that they were equal. A nonce, if there
+/// is one, is passed as the last associated data component. Sealed messages are the 16-byte
+/// synthetic IV followed by the ciphertext.
+pub struct Siv<C> {
+    mac_cipher: C,
+    ctr_cipher: C,
+}
+
+/// AES-SIV with a 256-bit key (`AEAD_AES_SIV_CMAC_256`), which is split into two AES-128 keys.
+pub type AesSiv256 = Siv<Aes128>;
+
+/// AES-SIV with a 512-bit key (`AEAD_AES_SIV_CMAC_512`), which is split into two AES-256 keys.
+pub type AesSiv512 = Siv<Aes256>;
+
+impl Siv<Aes128> {
+    /// Creates AES-SIV from a 256-bit key: the first half keys S2V and the second half CTR.
+    pub fn from_key(key: &Key256) -> Self {
+        let (mac_key, ctr_key) = key.as_bytes().split_at(16);
+        Siv::new(
+            Aes128::from_slice(mac_key).unwrap(),
+            Aes128::from_slice(ctr_key).unwrap(),
+        )
+    }
+}
+
+impl Siv<Aes256> {
+    /// Creates AES-SIV from a 512-bit key: the first half keys S2V and the second half CTR.
+    pub fn from_key(key: &Key<64>) -> Self {
+        let (mac_key, ctr_key) = key.as_bytes().split_at(32);
+        Siv::new(
+            Aes256::from_slice(mac_key).unwrap(),
+            Aes256::from_slice(ctr_key).unwrap(),
+        )
+    }
+}
+
+impl<C: BlockCipher> Siv<C> {
+    pub fn new(mac_cipher: C, ctr_cipher: C) -> Self {
+        Siv {
+            mac_cipher,
+            ctr_cipher,
+        }
+    }
+
+    /// Encrypts and authenticates `plaintext`, also authenticating each of the `associated_data`
+    /// components in order. Returns the synthetic IV followed by the ciphertext.
+    ///
+    /// Fails with [`Error::InputTooLong`], holding the count, if there are more than 126
+    /// components.
+    pub fn seal(&self, associated_data: &[&[u8]], plaintext: &[u8]) -> Result<Vec<u8>> {
+        let iv = self.s2v(associated_data, plaintext)?;
+
+        let mut output = Vec::with_capacity(BLOCK_SIZE + plaintext.len());
+        output.extend_from_slice(&iv);
+        output.extend_from_slice(plaintext);
+        self.ctr(&iv, &mut output[BLOCK_SIZE..]);
+
+        Ok(output)
+    }
+
+    /// Verifies and decrypts a message produced by [`Siv::seal`] with the same associated data.
+    ///
+    /// The decrypted plaintext is only returned if the synthetic IV matches, and the comparison
+    /// takes the same time wherever the IVs differ.
+    pub fn open(&self, associated_data: &[&[u8]], sealed: &[u8]) -> Result<Vec<u8>> {
+        if sealed.len() < BLOCK_SIZE {
+            return Err(Error::TruncatedInput);
+        }
+
+        let (received_iv, ciphertext) = sealed.split_at(BLOCK_SIZE);
+        let received_iv: &[u8; BLOCK_SIZE] = received_iv.try_into().unwrap();
+
+        // The IV is a MAC over the plaintext, so the payload has to be decrypted before it can
+        // be checked. It is wiped again if the IV does not match.
+        let mut output = ciphertext.to_vec();
+        self.ctr(received_iv, &mut output);
+        let expected_iv = self.s2v(associated_data, &output)?;
+
+        if !constant_time_eq(&expected_iv, received_iv) {
+            output.fill(0);
+            return Err(Error::AuthenticationFailed);
+        }
+
+        Ok(output)
+    }
+
+    /// The S2V construction: folds the CMAC of each associated data component into a running
+    /// value with doubling, then mixes in the plaintext for the final CMAC.
+    fn s2v(&self, associated_data: &[&[u8]], plaintext: &[u8]) -> Result<[u8; BLOCK_SIZE]> {
+        if associated_data.len() > MAX_COMPONENTS {
+            return Err(Error::InputTooLong(associated_data.len()));
+        }
+
+        let cmac = Cmac::new(&self.mac_cipher);
+        let mut d = cmac.mac(&[0; BLOCK_SIZE]);
+        for component in associated_data {
+            d = double(&d);
+            xor_block(&mut d, &cmac.mac(component));
+        }
+
+        if plaintext.len() >= BLOCK_SIZE {
+            // XOR D into the last block of a copy of the plaintext, wiped once it is hashed.
+            let mut t = Zeroizing::new(plaintext.to_vec());
+            let end = t.len() - BLOCK_SIZE;
+            for (byte, d) in t[end..].iter_mut().zip(&d) {
+                *byte ^= d;
+            }
+            Ok(cmac.mac(&t))
+        } else {
+            let mut padded = Zeroizing::new([0; BLOCK_SIZE]);
+            padded[..plaintext.len()].copy_from_slice(plaintext);
+            padded[plaintext.len()] = 0x80;
+            let mut last = double(&d);
+            xor_block(&mut last, &padded);
+            Ok(cmac.mac(&last))
+        }
+    }
+
+    /// XORs the CTR keystream into `data`. The counter starts at the IV with the top bit of its
+    /// last two 32-bit words cleared, so implementations with 32- or 64-bit counters agree.
+    fn ctr(&self, iv: &[u8; BLOCK_SIZE], data: &mut [u8]) {
+        let mut counter = *iv;
+        counter[8] &= 0x7f;
+        counter[12] &= 0x7f;
+        Ctr::new(&self.ctr_cipher, &counter).apply_keystream(data);
+    }
+}
+
+/// CMAC (NIST SP 800-38B), with the two subkeys derived up front.
+struct Cmac<'a, C> {
+    cipher: &'a C,
+    /// Masks the last block when it is complete.
+    k1: [u8; BLOCK_SIZE],
+    /// Masks the last block when it had to be padded.
+    k2: [u8; BLOCK_SIZE],
+}
+
+impl<'a, C: BlockCipher> Cmac<'a, C> {
+    fn new(cipher: &'a C) -> Self {
+        let k1 = double(&cipher.encrypt_block(&[0; BLOCK_SIZE]));
+        let k2 = double(&k1);
+        Cmac { cipher, k1, k2 }
+    }
+
+    fn mac(&self, data: &[u8]) -> [u8; BLOCK_SIZE] {
+        // The last block is always handled apart from the rest, even when it is empty.
+        let last_len = match data.len() % BLOCK_SIZE {
+            0 if !data.is_empty() => BLOCK_SIZE,
+            partial => partial,
+        };
+        let (blocks, last) = data.split_at(data.len() - last_len);
+
+        let mut state = [0; BLOCK_SIZE];
+        for block in blocks.chunks_exact(BLOCK_SIZE) {
+            xor_block(&mut state, block.try_into().unwrap());
+            state = self.cipher.encrypt_block(&state);
+        }
+
+        let mut last_block = [0; BLOCK_SIZE];
+        last_block[..last.len()].copy_from_slice(last);
+        if last.len() == BLOCK_SIZE {
+            xor_block(&mut last_block, &self.k1);
+        } else {
+            last_block[last.len()] = 0x80;
+            xor_block(&mut last_block, &self.k2);
+        }
+
+        xor_block(&mut state, &last_block);
+        self.cipher.encrypt_block(&state)
+    }
+}
+
+/// Multiplies a block by x in GF(2^128) with the CMAC polynomial, reading it as a big-endian
+/// integer. The reduction is applied without a branch on the secret top bit.
+fn double(block: &[u8; BLOCK_SIZE]) -> [u8; BLOCK_SIZE] {
+    let value = u128::from_be_bytes(*block);
+    ((value << 1) ^ ((value >> 127) * 0x87)).to_be_bytes()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::{check_rejects_tampering, hex, hex_array};
+
+    #[test]
+    fn test_rfc5297_deterministic() {
+        // Appendix A.1.
+        let siv = AesSiv256::from_key(&Key::new(hex_array(
+            "fffefdfcfbfaf9f8f7f6f5f4f3f2f1f0 f0f1f2f3f4f5f6f7f8f9fafbfcfdfeff",
+        )));
+        let aad = hex("101112131415161718191a1b1c1d1e1f 2021222324252627");
+        let plaintext = hex("112233445566778899aabbccddee");
+
+        let sealed = siv.seal(&[&aad], &plaintext).unwrap();
+        assert_eq!(
+            sealed,
+            hex("85632d07c6e8f37f950acd320a2ecc93 40c02b9690c4dc04daef7f6afe5c")
+        );
+        assert_eq!(siv.open(&[&aad], &sealed).unwrap(), plaintext);
+    }
+
+    #[test]
+    fn test_rfc5297_nonce_based() {
+        // Appendix A.2, with two associated data components followed by the nonce.
+        let siv = AesSiv256::from_key(&Key::new(hex_array(
+            "7f7e7d7c7b7a79787776757473727170 404142434445464748494a4b4c4d4e4f",
+        )));
+        let ad1 = hex(
+            "00112233445566778899aabbccddeeff deaddadadeaddadaffeeddccbbaa9988
+             7766554433221100",
+        );
+        let ad2 = hex("102030405060708090a0");
+        let nonce = hex("09f911029d74e35bd84156c5635688c0");
+        let plaintext = hex(
+            "7468697320697320736f6d6520706c61 696e7465787420746f20656e63727970
+             74207573696e67205349562d414553",
+        );
+        let components: [&[u8]; 3] = [&ad1, &ad2, &nonce];
+
+        let sealed = siv.seal(&components, &plaintext).unwrap();
+        assert_eq!(
+            sealed,
+            hex(
+                "7bdb6e3b432667eb06f4d14bff2fbd0f cb900f2fddbe404326601965c889bf17
+                 dba77ceb094fa663b7a3f748ba8af829 ea64ad544a272e9c485b62a3fd5c0d"
+            )
+        );
+        assert_eq!(siv.open(&components, &sealed).unwrap(), plaintext);
+    }
+
+    #[test]
+    fn test_aes_siv_512() {
+        // The IVs were computed with another implementation.
+        let siv = AesSiv512::from_key(&Key::new(std::array::from_fn(|i| i as u8)));
+        let plaintext: Vec<u8> = (0..300).map(|i| (i * 7) as u8).collect();
+        let components: [&[u8]; 3] = [b"header", b"", &[b'x'; 40]];
+
+        let sealed = siv.seal(&components, &plaintext).unwrap();
+        assert_eq!(sealed[..16], hex("561015dada0258f49d027f137ce47867"));
+        assert_eq!(siv.open(&components, &sealed).unwrap(), plaintext);
+
+        let siv = AesSiv256::from_key(&Key::new(std::array::from_fn(|i| i as u8)));
+        let sealed = siv.seal(&[], &[]).unwrap();
+        assert_eq!(sealed, hex("6890e5685ed0253753a2121dab850fdf"));
+        assert_eq!(siv.open(&[], &sealed).unwrap(), []);
+    }
+
+    #[test]
+    fn test_open_rejects_tampering() {
+        let siv = AesSiv256::from_key(&Key::new([7; 32]));
+        let sealed = check_rejects_tampering(&siv, b"nonce", b"aad", b"attack at dawn");
+
+        // The components are bound in order and by count.
+        assert!(siv.open(&[b"nonce", b"aad"], &sealed).is_err());
+        assert!(siv.open(&[b"aadnonce"], &sealed).is_err());
+        assert!(siv.open(&[b"aad", b"nonce", b""], &sealed).is_err());
+        assert!(matches!(
+            siv.open(&[b"aad", b"nonce"], &sealed[..15]),
+            Err(Error::TruncatedInput)
+        ));
+    }
+
+    #[test]
+    fn test_too_many_components() {
+        let siv = AesSiv256::from_key(&Key::new([7; 32]));
+        let components = [&b"ad"[..]; 127];
+        assert!(siv.seal(&components[..126], b"data").is_ok());
+        assert!(matches!(
+            siv.seal(&components, b"data"),
+            Err(Error::InputTooLong(127))
+        ));
+    }
+}
@@ -0,0 +1,423 @@
+// This file holds the `io::Write` and `io::Read` adapters around the CBC stream format used by
+// `cbc::encrypt_stream` and `cbc::decrypt_stream`: a version byte and a random IV followed by the
+// padded ciphertext.
+
+use std::io::{self, Read, Write};
+
+use crate::cbc::{
+    generate_iv, parse_stream_header, stream_header, PaddedDecryptor, PaddedEncryptor, HEADER_SIZE,
+    STREAM_CHUNK_SIZE,
+};
+use crate::cipher::BlockCipher;
+use crate::error::{Error, Result};
+
+/// Encrypts everything written to it into the inner writer.
+///
+/// The padded final block can only be produced once the plaintext is complete, so the output is
+/// not valid until [`EncryptingWriter::finish`] has been called. Dropping the writer without
+/// finishing it leaves a truncated stream that will fail to decrypt.
+///
+/// A write only fails if the inner writer fails before any of the new plaintext is taken. Once
+/// plaintext has been encrypted the write succeeds, and ciphertext the inner writer did not
+/// accept is kept and sent first by the next write, flush or finish. Retrying after an error is
+/// therefore safe.
+pub struct EncryptingWriter<W, C> {
+    inner: W,
+    encryptor: PaddedEncryptor<C>,
+    output: Vec<u8>,
+    /// How much of `output` the inner writer has already accepted.
+    written: usize,
+}
+
+impl<W: Write, C: BlockCipher> EncryptingWriter<W, C> {
+    /// Starts a new stream under a random IV. Nothing is written to `inner` until the first
+    /// write, flush or finish.
+    pub fn new(inner: W, cipher: C) -> Self {
+        let iv = generate_iv();
+        EncryptingWriter {
+            inner,
+            encryptor: PaddedEncryptor::new(cipher, &iv),
+            output: stream_header(&iv).to_vec(),
+            written: 0,
+        }
+    }
+
+    /// Writes the final padded block, flushes the inner writer and returns it.
+    pub fn finish(mut self) -> Result<W> {
+        self.write_output()?;
+        self.encryptor.finish(&mut self.output);
+        self.inner.write_all(&self.output)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    /// Sends the ciphertext the inner writer has not accepted yet, keeping track of partial
+    /// writes so that nothing is sent twice if it fails part way.
+    fn write_output(&mut self) -> io::Result<()> {
+        while self.written < self.output.len() {
+            match self.inner.write(&self.output[self.written..]) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => self.written += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        self.output.clear();
+        self.written = 0;
+        Ok(())
+    }
+}
+
+impl<W: Write, C: BlockCipher> Write for EncryptingWriter<W, C> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // Earlier ciphertext goes first; if it cannot be sent, none of `buf` is taken.
+        self.write_output()?;
+
+        self.encryptor.update(buf, &mut self.output);
+        // The plaintext has been taken, so an error here is left for the next call to report.
+        let _ = self.write_output();
+        Ok(buf.len())
+    }
+
+    /// Flushes the inner writer. Up to 15 bytes of plaintext stay buffered until a full block
+    /// is available or the stream is finished.
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_output()?;
+        self.inner.flush()
+    }
+}
+
+/// Decrypts a stream produced by [`EncryptingWriter`] or `cbc::encrypt_stream`, yielding
+/// plaintext as it is read.
+///
+/// The last block of plaintext is held back until the end of the ciphertext is reached, because
+/// only then can its padding be checked. A truncated stream or bad padding is reported as an
+/// error from the read that reaches the end; every read after a failure fails as well, so the
+/// error cannot be mistaken for a clean end of stream.
+///
+/// Errors are returned as `io::Error`s as `io::Read` requires. `Error::from` recovers the
+/// original [`Error`] from them.
+pub struct DecryptingReader<R, C> {
+    inner: R,
+    state: State<C>,
+    output: Vec<u8>,
+    position: usize,
+}
+
+enum State<C> {
+    Header {
+        cipher: C,
+        header: [u8; HEADER_SIZE],
+        header_len: usize,
+    },
+    Body(PaddedDecryptor<C>),
+    Finished,
+    Failed,
+}
+
+impl<R: Read, C: BlockCipher> DecryptingReader<R, C> {
+    /// Wraps `inner`. Nothing is read from it until the first read.
+    pub fn new(inner: R, cipher: C) -> Self {
+        DecryptingReader {
+            inner,
+            state: State::Header {
+                cipher,
+                header: [0; HEADER_SIZE],
+                header_len: 0,
+            },
+            output: Vec::with_capacity(STREAM_CHUNK_SIZE),
+            position: 0,
+        }
+    }
+
+    /// Returns the inner reader, discarding any plaintext that has not been read yet.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Reads and decrypts more of the stream into `output`, which must be empty.
+    fn fill_output(&mut self) -> Result<()> {
+        match &mut self.state {
+            // The header is kept across calls, so a read that fails part way through it loses
+            // nothing.
+            State::Header {
+                header, header_len, ..
+            } => {
+                let read_size = match self.inner.read(&mut header[*header_len..]) {
+                    Ok(n) => n,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => return Ok(()),
+                    Err(e) => return Err(e.into()),
+                };
+                *header_len += read_size;
+
+                if read_size == 0 {
+                    self.state = State::Failed;
+                    return Err(Error::TruncatedInput);
+                }
+
+                if *header_len == HEADER_SIZE {
+                    if let State::Header { cipher, header, .. } =
+                        std::mem::replace(&mut self.state, State::Failed)
+                    {
+                        let iv = parse_stream_header(&header)?;
+                        self.state = State::Body(PaddedDecryptor::new(cipher, &iv));
+                    }
+                }
+            }
+            State::Body(decryptor) => {
+                let mut buffer = [0u8; STREAM_CHUNK_SIZE];
+                let read_size = self.inner.read(&mut buffer)?;
+                if read_size != 0 {
+                    decryptor.update(&buffer[..read_size], &mut self.output);
+                    return Ok(());
+                }
+
+                if let State::Body(decryptor) = std::mem::replace(&mut self.state, State::Failed) {
+                    decryptor.finish(&mut self.output)?;
+                    self.state = State::Finished;
+                }
+            }
+            State::Finished => {}
+            State::Failed => {
+                return Err(Error::Io(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "stream has already failed to decrypt",
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<R: Read, C: BlockCipher> Read for DecryptingReader<R, C> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        while self.position == self.output.len() {
+            if let State::Finished = self.state {
+                return Ok(0);
+            }
+            self.output.clear();
+            self.position = 0;
+            self.fill_output()?;
+        }
+
+        let len = buf.len().min(self.output.len() - self.position);
+        buf[..len].copy_from_slice(&self.output[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::cbc;
+    use crate::cipher::Aes256;
+    use crate::key::Key;
+    use crate::test_util::ChunkedReader;
+
+    #[test]
+    fn test_writer_output_decrypts_with_stream_functions() {
+        let cipher = Aes256::new(&Key::new([9; 32]));
+
+        for len in [0, 1, 15, 16, 17, 1000] {
+            let plaintext: Vec<u8> = (0..len).map(|i| (i * 3) as u8).collect();
+
+            let mut writer = EncryptingWriter::new(Vec::new(), &cipher);
+            for piece in plaintext.chunks(7) {
+                writer.write_all(piece).unwrap();
+            }
+            let encrypted = writer.finish().unwrap();
+            assert_eq!(encrypted.len(), HEADER_SIZE + (len / 16 + 1) * 16);
+
+            let mut decrypted = Vec::new();
+            cbc::decrypt_stream(Cursor::new(&encrypted), &mut decrypted, &cipher).unwrap();
+            assert_eq!(decrypted, plaintext);
+        }
+    }
+
+    /// Accepts at most five bytes per write and fails some writes until its failures run out.
+    struct FlakyWriter {
+        data: Vec<u8>,
+        calls: usize,
+        failures_left: usize,
+    }
+
+    impl Write for FlakyWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.calls += 1;
+            if self.failures_left > 0 && self.calls.is_multiple_of(3) {
+                self.failures_left -= 1;
+                return Err(io::ErrorKind::BrokenPipe.into());
+            }
+            let len = buf.len().min(5);
+            self.data.extend_from_slice(&buf[..len]);
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_writer_retries_after_inner_errors() {
+        let cipher = Aes256::new(&Key::new([9; 32]));
+        let plaintext: Vec<u8> = (0..1000).map(|i| (i * 3) as u8).collect();
+
+        let inner = FlakyWriter {
+            data: Vec::new(),
+            calls: 0,
+            failures_left: 20,
+        };
+        let mut writer = EncryptingWriter::new(inner, &cipher);
+        let mut errors = 0;
+        for piece in plaintext.chunks(7) {
+            let mut remaining = piece;
+            while !remaining.is_empty() {
+                match writer.write(remaining) {
+                    Ok(n) => remaining = &remaining[n..],
+                    Err(_) => errors += 1,
+                }
+            }
+        }
+        while writer.flush().is_err() {
+            errors += 1;
+        }
+        let encrypted = writer.finish().unwrap().data;
+        assert!(errors > 0);
+
+        let mut decrypted = Vec::new();
+        cbc::decrypt_stream(Cursor::new(&encrypted), &mut decrypted, &cipher).unwrap();
+        assert_eq!(decrypted, plaintext);
+    }
+
+    #[test]
+    fn test_writer_with_formatting_macros() {
+        let cipher = Aes256::new(&Key::new([9; 32]));
+
+        let mut writer = EncryptingWriter::new(Vec::new(), &cipher);
+        for line in 0..100 {
+            writeln!(writer, "line {}", line).unwrap();
+        }
+        writer.flush().unwrap();
+        let encrypted = writer.finish().unwrap();
+
+        let expected: String = (0..100).map(|line| format!("line {}\n", line)).collect();
+        let mut decrypted = Vec::new();
+        cbc::decrypt_stream(Cursor::new(&encrypted), &mut decrypted, &cipher).unwrap();
+        assert_eq!(decrypted, expected.as_bytes());
+    }
+
+    #[test]
+    fn test_reader_round_trip() {
+        let cipher = Aes256::new(&Key::new([9; 32]));
+
+        for len in [0, 1, 15, 16, 17, 1000, 5000] {
+            let plaintext: Vec<u8> = (0..len).map(|i| (i * 3) as u8).collect();
+            let mut encrypted = Vec::new();
+            cbc::encrypt_stream(Cursor::new(&plaintext), &mut encrypted, &cipher).unwrap();
+
+            let mut decrypted = Vec::new();
+            DecryptingReader::new(Cursor::new(&encrypted), &cipher)
+                .read_to_end(&mut decrypted)
+                .unwrap();
+            assert_eq!(decrypted, plaintext);
+
+            // Short reads on both sides of the adapter.
+            let source = ChunkedReader::random(&encrypted, len as u64).interrupting();
+            let mut reader = DecryptingReader::new(source, &cipher);
+            let mut decrypted = Vec::new();
+            let mut piece = [0u8; 5];
+            loop {
+                match reader.read(&mut piece) {
+                    Ok(0) => break,
+                    Ok(n) => decrypted.extend_from_slice(&piece[..n]),
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => panic!("{}", e),
+                }
+            }
+            assert_eq!(decrypted, plaintext);
+        }
+    }
+
+    #[test]
+    fn test_reader_resumes_after_error_inside_header() {
+        let cipher = Aes256::new(&Key::new([9; 32]));
+        let plaintext: Vec<u8> = (0..100).collect();
+        let mut encrypted = Vec::new();
+        cbc::encrypt_stream(Cursor::new(&plaintext), &mut encrypted, &cipher).unwrap();
+
+        for fail_at in [1, HEADER_SIZE / 2, HEADER_SIZE - 1] {
+            let source = ChunkedReader::fixed(&encrypted, 3).failing_at(fail_at);
+            let mut reader = DecryptingReader::new(source, &cipher);
+            let mut decrypted = Vec::new();
+
+            let error = reader.read_to_end(&mut decrypted).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::WouldBlock);
+            reader.read_to_end(&mut decrypted).unwrap();
+            assert_eq!(decrypted, plaintext);
+        }
+    }
+
+    #[test]
+    fn test_reader_reports_errors_at_the_end() {
+        let cipher = Aes256::new(&Key::new([9; 32]));
+        let plaintext = [7u8; 100];
+        let mut encrypted = Vec::new();
+        cbc::encrypt_stream(Cursor::new(&plaintext), &mut encrypted, &cipher).unwrap();
+
+        // A final block ending in zero can never carry valid padding.
+        let iv = [4; 16];
+        let mut encryptor = cbc::CbcEncryptor::new(&cipher, &iv);
+        let mut bad_padding = stream_header(&iv).to_vec();
+        bad_padding.extend_from_slice(&encryptor.encrypt_block(&[7; 16]));
+        bad_padding.extend_from_slice(&encryptor.encrypt_block(&[0; 16]));
+
+        let mut reader = DecryptingReader::new(Cursor::new(&bad_padding), &cipher);
+        let error = reader.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(matches!(Error::from(error), Error::BadPadding));
+        assert!(reader.read(&mut [0u8; 16]).is_err());
+
+        let truncated = &encrypted[..encrypted.len() - 1];
+        let mut reader = DecryptingReader::new(Cursor::new(truncated), &cipher);
+        let mut decrypted = Vec::new();
+        let error = reader.read_to_end(&mut decrypted).unwrap_err();
+        assert!(matches!(Error::from(error), Error::TruncatedInput));
+        assert!(decrypted.len() < plaintext.len());
+
+        let mut reader = DecryptingReader::new(Cursor::new(&encrypted[..10]), &cipher);
+        let error = reader.read(&mut [0u8; 16]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+
+        let mut future = encrypted.clone();
+        future[0] = cbc::FORMAT_VERSION + 1;
+        let mut reader = DecryptingReader::new(Cursor::new(&future), &cipher);
+        let error = reader.read(&mut [0u8; 16]).unwrap_err();
+        assert!(matches!(Error::from(error), Error::UnsupportedVersion(2)));
+        assert!(reader.read(&mut [0u8; 16]).is_err());
+    }
+
+    #[test]
+    fn test_writer_to_reader() {
+        let cipher = Aes256::new(&Key::new([1; 32]));
+        let text = "a line of text that spans a few blocks\n".repeat(20);
+
+        let mut writer = EncryptingWriter::new(Vec::new(), &cipher);
+        writer.write_all(text.as_bytes()).unwrap();
+        let encrypted = writer.finish().unwrap();
+
+        let mut decrypted = String::new();
+        DecryptingReader::new(Cursor::new(encrypted), &cipher)
+            .read_to_string(&mut decrypted)
+            .unwrap();
+        assert_eq!(decrypted, text);
+    }
+}
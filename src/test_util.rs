@@ -0,0 +1,185 @@
+// This file holds helpers shared by the unit tests for decoding published test vectors.
+
+use crate::ccm::Ccm;
+use crate::cipher::BlockCipher;
+use crate::error::{Error, Result};
+use crate::gcm::Gcm;
+use crate::gcm_siv::Aes256GcmSiv;
+use crate::siv::Siv;
+
+/// Decodes a hex string, ignoring any whitespace used to split it into blocks.
+pub fn hex(value: &str) -> Vec<u8> {
+    let digits: Vec<u8> = value.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+    digits
+        .chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).unwrap(), 16).unwrap())
+        .collect()
+}
+
+/// Decodes a hex string that must describe exactly `N` bytes.
+pub fn hex_array<const N: usize>(value: &str) -> [u8; N] {
+    hex(value).try_into().unwrap()
+}
+
+/// A reader that returns its data in short pieces, the way pipes and sockets do, optionally
+/// failing with `Interrupted` before every piece.
+pub struct ChunkedReader {
+    data: Vec<u8>,
+    position: usize,
+    sizes: Vec<usize>,
+    next_size: usize,
+    interrupt: bool,
+    interrupted: bool,
+    fail_at: Option<usize>,
+}
+
+impl ChunkedReader {
+    /// Returns at most `size` bytes from each read.
+    pub fn fixed(data: &[u8], size: usize) -> Self {
+        ChunkedReader::with_sizes(data, vec![size])
+    }
+
+    /// Returns a pseudo-random number of bytes, from 1 to 40, from each read.
+    pub fn random(data: &[u8], seed: u64) -> Self {
+        use rand::{rngs::StdRng, Rng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(seed);
+        ChunkedReader::with_sizes(data, (0..97).map(|_| rng.gen_range(1..=40)).collect())
+    }
+
+    fn with_sizes(data: &[u8], sizes: Vec<usize>) -> Self {
+        ChunkedReader {
+            data: data.to_vec(),
+            position: 0,
+            sizes,
+            next_size: 0,
+            interrupt: false,
+            interrupted: false,
+            fail_at: None,
+        }
+    }
+
+    /// Makes every other read fail with `ErrorKind::Interrupted`.
+    pub fn interrupting(mut self) -> Self {
+        self.interrupt = true;
+        self
+    }
+
+    /// Makes the read that reaches `position` stop there, and the read after it fail once with
+    /// `ErrorKind::WouldBlock`.
+    pub fn failing_at(mut self, position: usize) -> Self {
+        self.fail_at = Some(position);
+        self
+    }
+}
+
+impl std::io::Read for ChunkedReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.interrupt && !self.interrupted {
+            self.interrupted = true;
+            return Err(std::io::ErrorKind::Interrupted.into());
+        }
+        self.interrupted = false;
+
+        if self.fail_at == Some(self.position) {
+            self.fail_at = None;
+            return Err(std::io::ErrorKind::WouldBlock.into());
+        }
+        let limit = match self.fail_at {
+            Some(fail_at) => fail_at - self.position,
+            None => self.data.len() - self.position,
+        };
+
+        let size = self.sizes[self.next_size % self.sizes.len()];
+        self.next_size += 1;
+
+        let len = size.min(buf.len()).min(limit);
+        buf[..len].copy_from_slice(&self.data[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+/// The seal and open calls of the authenticated modes, so their tests can share fixtures.
+pub trait Aead {
+    fn seal(&self, nonce: &[u8], aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>>;
+    fn open(&self, nonce: &[u8], aad: &[u8], sealed: &[u8]) -> Result<Vec<u8>>;
+}
+
+macro_rules! impl_aead {
+    ($($ty:ty),*) => {
+        $(
+            impl<C: BlockCipher> Aead for $ty {
+                fn seal(&self, nonce: &[u8], aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+                    <$ty>::seal(self, nonce, aad, plaintext)
+                }
+
+                fn open(&self, nonce: &[u8], aad: &[u8], sealed: &[u8]) -> Result<Vec<u8>> {
+                    <$ty>::open(self, nonce, aad, sealed)
+                }
+            }
+        )*
+    };
+}
+
+impl_aead!(Gcm<C>, Ccm<C>);
+
+impl Aead for Aes256GcmSiv {
+    fn seal(&self, nonce: &[u8], aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+        Aes256GcmSiv::seal(self, nonce, aad, plaintext)
+    }
+
+    fn open(&self, nonce: &[u8], aad: &[u8], sealed: &[u8]) -> Result<Vec<u8>> {
+        Aes256GcmSiv::open(self, nonce, aad, sealed)
+    }
+}
+
+/// SIV takes the nonce as the last associated data component.
+impl<C: BlockCipher> Aead for Siv<C> {
+    fn seal(&self, nonce: &[u8], aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+        Siv::seal(self, &[aad, nonce], plaintext)
+    }
+
+    fn open(&self, nonce: &[u8], aad: &[u8], sealed: &[u8]) -> Result<Vec<u8>> {
+        Siv::open(self, &[aad, nonce], sealed)
+    }
+}
+
+/// Checks a known-answer vector: sealing must give `sealed`, and opening it must give back
+/// `plaintext`.
+pub fn check_aead<A: Aead>(aead: &A, nonce: &[u8], aad: &[u8], plaintext: &[u8], sealed: &[u8]) {
+    let output = aead.seal(nonce, aad, plaintext).unwrap();
+    assert_eq!(output, sealed);
+    assert_eq!(aead.open(nonce, aad, &output).unwrap(), plaintext);
+}
+
+/// Seals `plaintext`, then checks that flipping a bit in any byte of the result, or changing the
+/// associated data, fails authentication. Returns the sealed message for mode-specific checks.
+pub fn check_rejects_tampering<A: Aead>(
+    aead: &A,
+    nonce: &[u8],
+    aad: &[u8],
+    plaintext: &[u8],
+) -> Vec<u8> {
+    let sealed = aead.seal(nonce, aad, plaintext).unwrap();
+    assert_eq!(aead.open(nonce, aad, &sealed).unwrap(), plaintext);
+
+    for i in 0..sealed.len() {
+        let mut tampered = sealed.clone();
+        tampered[i] ^= 0x80;
+        assert!(
+            matches!(
+                aead.open(nonce, aad, &tampered),
+                Err(Error::AuthenticationFailed)
+            ),
+            "byte {}",
+            i
+        );
+    }
+    assert!(matches!(
+        aead.open(nonce, b"other aad", &sealed),
+        Err(Error::AuthenticationFailed)
+    ));
+
+    sealed
+}
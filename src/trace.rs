@@ -0,0 +1,200 @@
+// This file holds the opt-in observer used to trace the intermediate values of the cipher.
+
+use std::fmt::Write;
+
+/// A step of the cipher, named after the rows of the FIPS-197 Appendix C listings.
+///
+/// The `I*` variants are the steps of the equivalent inverse cipher.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Step {
+    Input,
+    Start,
+    SBox,
+    SRow,
+    MCol,
+    KSch,
+    Output,
+    IInput,
+    IStart,
+    ISBox,
+    ISRow,
+    IMCol,
+    IKSch,
+    IOutput,
+}
+
+impl Step {
+    /// The label used for this step in FIPS-197 Appendix C.
+    pub fn name(self) -> &'static str {
+        match self {
+            Step::Input => "input",
+            Step::Start => "start",
+            Step::SBox => "s_box",
+            Step::SRow => "s_row",
+            Step::MCol => "m_col",
+            Step::KSch => "k_sch",
+            Step::Output => "output",
+            Step::IInput => "iinput",
+            Step::IStart => "istart",
+            Step::ISBox => "is_box",
+            Step::ISRow => "is_row",
+            Step::IMCol => "im_col",
+            Step::IKSch => "ik_sch",
+            Step::IOutput => "ioutput",
+        }
+    }
+}
+
+/// Receives every intermediate state and round key while a block is processed.
+///
+/// `()` is the silent observer used by the normal encrypt and decrypt paths, and any
+/// `FnMut(usize, Step, &[u8; 16])` closure can be used to collect a trace.
+pub trait RoundObserver {
+    fn observe(&mut self, round: usize, step: Step, value: &[u8; 16]);
+}
+
+impl RoundObserver for () {
+    #[inline(always)]
+    fn observe(&mut self, _round: usize, _step: Step, _value: &[u8; 16]) {}
+}
+
+impl<F: FnMut(usize, Step, &[u8; 16])> RoundObserver for F {
+    fn observe(&mut self, round: usize, step: Step, value: &[u8; 16]) {
+        self(round, step, value)
+    }
+}
+
+/// Formats a single observation the way FIPS-197 Appendix C prints it, e.g.
+/// `round[ 1].start  00102030405060708090a0b0c0d0e0f0`.
+pub fn format_step(round: usize, step: Step, value: &[u8; 16]) -> String {
+    let mut line = format!("round[{:2}].{:<8}", round, step.name());
+    for byte in value {
+        write!(line, "{:02x}", byte).unwrap();
+    }
+    line
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cipher::Aes128;
+    use crate::key::Key;
+    use crate::test_util::hex_array as hex;
+
+    fn fips_197_c1() -> (Aes128, [u8; 16]) {
+        let mut key = [0; 16];
+        for (i, byte) in key.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        (
+            Aes128::new(&Key::new(key)),
+            hex("00112233445566778899aabbccddeeff"),
+        )
+    }
+
+    #[test]
+    fn test_format_step() {
+        let value = hex("00102030405060708090a0b0c0d0e0f0");
+        assert_eq!(
+            format_step(1, Step::Start, &value),
+            "round[ 1].start   00102030405060708090a0b0c0d0e0f0"
+        );
+        assert_eq!(
+            format_step(10, Step::IOutput, &value),
+            "round[10].ioutput 00102030405060708090a0b0c0d0e0f0"
+        );
+    }
+
+    #[test]
+    fn test_encrypt_trace() {
+        let (cipher, plaintext) = fips_197_c1();
+        let mut trace = Vec::new();
+        let output = cipher
+            .encrypt_block_traced(&plaintext, &mut |round, step, value: &[u8; 16]| {
+                trace.push((round, step, *value))
+            });
+
+        // input + k_sch, five steps for each of the nine full rounds, then the last round's
+        // start, s_box, s_row, k_sch and output.
+        assert_eq!(trace.len(), 2 + 9 * 5 + 5);
+        assert_eq!(trace[0], (0, Step::Input, plaintext));
+        assert_eq!(
+            trace[1],
+            (0, Step::KSch, hex("000102030405060708090a0b0c0d0e0f"))
+        );
+        assert_eq!(
+            trace[2],
+            (1, Step::Start, hex("00102030405060708090a0b0c0d0e0f0"))
+        );
+        assert_eq!(
+            trace[3],
+            (1, Step::SBox, hex("63cab7040953d051cd60e0e7ba70e18c"))
+        );
+        assert_eq!(
+            trace[4],
+            (1, Step::SRow, hex("6353e08c0960e104cd70b751bacad0e7"))
+        );
+        assert_eq!(
+            trace[5],
+            (1, Step::MCol, hex("5f72641557f5bc92f7be3b291db9f91a"))
+        );
+        assert_eq!(
+            trace[6],
+            (1, Step::KSch, hex("d6aa74fdd2af72fadaa678f1d6ab76fe"))
+        );
+        assert_eq!(
+            trace[7],
+            (2, Step::Start, hex("89d810e8855ace682d1843d8cb128fe4"))
+        );
+
+        let last = trace.len() - 1;
+        assert_eq!(
+            trace[last - 1],
+            (10, Step::KSch, hex("13111d7fe3944a17f307a78b4d2b30c5"))
+        );
+        assert_eq!(trace[last], (10, Step::Output, output));
+        assert_eq!(output, hex("69c4e0d86a7b0430d8cdb78070b4c55a"));
+    }
+
+    #[test]
+    fn test_decrypt_trace() {
+        let (cipher, plaintext) = fips_197_c1();
+        let ciphertext = hex("69c4e0d86a7b0430d8cdb78070b4c55a");
+        let mut trace = Vec::new();
+        let output = cipher
+            .decrypt_block_traced(&ciphertext, &mut |round, step, value: &[u8; 16]| {
+                trace.push((round, step, *value))
+            });
+
+        assert_eq!(trace.len(), 2 + 9 * 5 + 5);
+        assert_eq!(trace[0], (0, Step::IInput, ciphertext));
+        assert_eq!(
+            trace[1],
+            (0, Step::IKSch, hex("13111d7fe3944a17f307a78b4d2b30c5"))
+        );
+        assert_eq!(
+            trace[2],
+            (1, Step::IStart, hex("7ad5fda789ef4e272bca100b3d9ff59f"))
+        );
+        assert_eq!(
+            trace[3],
+            (1, Step::ISBox, hex("bdb52189f261b63d0b107c9e8b6e776e"))
+        );
+        assert_eq!(
+            trace[4],
+            (1, Step::ISRow, hex("bd6e7c3df2b5779e0b61216e8b10b689"))
+        );
+        assert_eq!(
+            trace[5],
+            (1, Step::IMCol, hex("4773b91ff72f354361cb018ea1e6cf2c"))
+        );
+
+        let last = trace.len() - 1;
+        assert_eq!(
+            trace[last - 1],
+            (10, Step::IKSch, hex("000102030405060708090a0b0c0d0e0f"))
+        );
+        assert_eq!(trace[last], (10, Step::IOutput, plaintext));
+        assert_eq!(output, plaintext);
+    }
+}
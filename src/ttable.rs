@@ -0,0 +1,290 @@
+// This file holds the T-table backend, the classic 32-bit software AES that merges SubBytes,
+// ShiftRows and MixColumns into four table lookups per column.
+//
+// It is much faster than the reference rounds but NOT constant time: every lookup is indexed by
+// secret state, so the cache access pattern can leak the key to an attacker sharing the machine.
+// It is therefore never used implicitly; callers opt in by constructing one of the ciphers here.
+//
+// State columns are held as big-endian words, matching the key schedule, so byte 0 of a column
+// is the most significant byte.
+
+use crate::{
+    cipher::BlockCipher,
+    error::Result,
+    key::{Key, KeySchedule},
+    shared::{inverse_key_expansion, key_expansion},
+};
+
+/// Multiplies two elements of GF(2^8). Only used to build the tables at compile time.
+const fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0;
+    while b != 0 {
+        if b & 1 == 1 {
+            product ^= a;
+        }
+        let carry = a & 0x80;
+        a <<= 1;
+        if carry != 0 {
+            a ^= 0x1b;
+        }
+        b >>= 1;
+    }
+    product
+}
+
+const fn build_sbox() -> [u8; 256] {
+    let mut sbox = [0; 256];
+    let mut x = 0;
+    while x < 256 {
+        // x^254 is the multiplicative inverse, with zero mapped to zero.
+        let mut inverse = 1;
+        let mut i = 0;
+        while i < 254 {
+            inverse = gf_mul(inverse, x as u8);
+            i += 1;
+        }
+        let b = inverse;
+        sbox[x] =
+            b ^ b.rotate_left(1) ^ b.rotate_left(2) ^ b.rotate_left(3) ^ b.rotate_left(4) ^ 0x63;
+        x += 1;
+    }
+    sbox
+}
+
+const fn build_inverse_sbox(sbox: &[u8; 256]) -> [u8; 256] {
+    let mut inverse = [0; 256];
+    let mut x = 0;
+    while x < 256 {
+        inverse[sbox[x] as usize] = x as u8;
+        x += 1;
+    }
+    inverse
+}
+
+/// Builds the table for column byte 0; the tables for bytes 1 to 3 are rotations of it.
+const fn build_table(sbox: &[u8; 256], coefficients: [u8; 4], rotation: u32) -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut x = 0;
+    while x < 256 {
+        let s = sbox[x];
+        let word = u32::from_be_bytes([
+            gf_mul(s, coefficients[0]),
+            gf_mul(s, coefficients[1]),
+            gf_mul(s, coefficients[2]),
+            gf_mul(s, coefficients[3]),
+        ]);
+        table[x] = word.rotate_right(rotation);
+        x += 1;
+    }
+    table
+}
+
+const SBOX: [u8; 256] = build_sbox();
+const INVERSE_SBOX: [u8; 256] = build_inverse_sbox(&SBOX);
+
+/// The first column of MixColumns, and of InvMixColumns, applied to a substituted byte.
+const MIX: [u8; 4] = [2, 1, 1, 3];
+const INVERSE_MIX: [u8; 4] = [14, 9, 13, 11];
+
+static TE: [[u32; 256]; 4] = [
+    build_table(&SBOX, MIX, 0),
+    build_table(&SBOX, MIX, 8),
+    build_table(&SBOX, MIX, 16),
+    build_table(&SBOX, MIX, 24),
+];
+
+static TD: [[u32; 256]; 4] = [
+    build_table(&INVERSE_SBOX, INVERSE_MIX, 0),
+    build_table(&INVERSE_SBOX, INVERSE_MIX, 8),
+    build_table(&INVERSE_SBOX, INVERSE_MIX, 16),
+    build_table(&INVERSE_SBOX, INVERSE_MIX, 24),
+];
+
+fn load(block: &[u8; 16]) -> [u32; 4] {
+    let mut columns = [0; 4];
+    for (column, bytes) in columns.iter_mut().zip(block.chunks_exact(4)) {
+        *column = u32::from_be_bytes(bytes.try_into().unwrap());
+    }
+    columns
+}
+
+fn store(columns: [u32; 4]) -> [u8; 16] {
+    let mut block = [0; 16];
+    for (bytes, column) in block.chunks_exact_mut(4).zip(columns) {
+        bytes.copy_from_slice(&column.to_be_bytes());
+    }
+    block
+}
+
+fn byte(word: u32, index: usize) -> usize {
+    (word >> (24 - 8 * index) & 0xFF) as usize
+}
+
+/// Runs the cipher with one lookup per byte and round. Column `c` of each round takes row `r`
+/// from column `c + r`, which is ShiftRows.
+fn encrypt(round_keys: &[u32], block: &[u8; 16]) -> [u8; 16] {
+    let nr = round_keys.len() / 4 - 1;
+    let mut s = load(block);
+    for (column, key) in s.iter_mut().zip(&round_keys[..4]) {
+        *column ^= key;
+    }
+
+    for round in 1..nr {
+        let key = &round_keys[4 * round..4 * round + 4];
+        let mut t = [0; 4];
+        for (c, column) in t.iter_mut().enumerate() {
+            *column = TE[0][byte(s[c], 0)]
+                ^ TE[1][byte(s[(c + 1) % 4], 1)]
+                ^ TE[2][byte(s[(c + 2) % 4], 2)]
+                ^ TE[3][byte(s[(c + 3) % 4], 3)]
+                ^ key[c];
+        }
+        s = t;
+    }
+
+    let key = &round_keys[4 * nr..];
+    let mut t = [0; 4];
+    for (c, column) in t.iter_mut().enumerate() {
+        *column = u32::from_be_bytes([
+            SBOX[byte(s[c], 0)],
+            SBOX[byte(s[(c + 1) % 4], 1)],
+            SBOX[byte(s[(c + 2) % 4], 2)],
+            SBOX[byte(s[(c + 3) % 4], 3)],
+        ]) ^ key[c];
+    }
+    store(t)
+}
+
+/// Runs the equivalent inverse cipher, whose inner round keys have had InvMixColumns applied.
+/// Row `r` of column `c` comes from column `c - r`, which is InvShiftRows.
+fn decrypt(round_keys: &[u32], block: &[u8; 16]) -> [u8; 16] {
+    let nr = round_keys.len() / 4 - 1;
+    let mut s = load(block);
+    for (column, key) in s.iter_mut().zip(&round_keys[4 * nr..]) {
+        *column ^= key;
+    }
+
+    for round in (1..nr).rev() {
+        let key = &round_keys[4 * round..4 * round + 4];
+        let mut t = [0; 4];
+        for (c, column) in t.iter_mut().enumerate() {
+            *column = TD[0][byte(s[c], 0)]
+                ^ TD[1][byte(s[(c + 3) % 4], 1)]
+                ^ TD[2][byte(s[(c + 2) % 4], 2)]
+                ^ TD[3][byte(s[(c + 1) % 4], 3)]
+                ^ key[c];
+        }
+        s = t;
+    }
+
+    let key = &round_keys[..4];
+    let mut t = [0; 4];
+    for (c, column) in t.iter_mut().enumerate() {
+        *column = u32::from_be_bytes([
+            INVERSE_SBOX[byte(s[c], 0)],
+            INVERSE_SBOX[byte(s[(c + 3) % 4], 1)],
+            INVERSE_SBOX[byte(s[(c + 2) % 4], 2)],
+            INVERSE_SBOX[byte(s[(c + 1) % 4], 3)],
+        ]) ^ key[c];
+    }
+    store(t)
+}
+
+macro_rules! ttable_cipher {
+    ($name:ident, $key_len:literal, $bits:literal) => {
+        #[doc = concat!("An AES-", $bits, " cipher on the T-table backend.")]
+        ///
+        #[doc = concat!("Produces exactly the same output as [`crate::", stringify!($name), "`], several times faster,")]
+        /// but its table lookups are indexed by secret data and are not constant time. Only use
+        /// it where cache-timing attacks are not a concern.
+        #[derive(Clone, Debug)]
+        pub struct $name {
+            round_keys: KeySchedule,
+            inverse_round_keys: KeySchedule,
+        }
+
+        impl $name {
+            #[doc = concat!("Expands both key schedules for the given ", $bits, "-bit key.")]
+            pub fn new(key: &Key<$key_len>) -> Self {
+                $name {
+                    round_keys: key_expansion(key.as_bytes()),
+                    inverse_round_keys: inverse_key_expansion(key.as_bytes()),
+                }
+            }
+
+            /// Expands the key schedules for a key of unchecked length. Fails with
+            #[doc = concat!("[`Error::InvalidKeyLength`](crate::Error::InvalidKeyLength) unless the key is ", $key_len, " bytes long.")]
+            pub fn from_slice(key: &[u8]) -> Result<Self> {
+                Ok($name::new(&Key::from_slice(key)?))
+            }
+        }
+
+        impl BlockCipher for $name {
+            fn encrypt_block(&self, data: &[u8; 16]) -> [u8; 16] {
+                encrypt(&self.round_keys, data)
+            }
+
+            fn decrypt_block(&self, data: &[u8; 16]) -> [u8; 16] {
+                decrypt(&self.inverse_round_keys, data)
+            }
+        }
+    };
+}
+
+ttable_cipher!(Aes128, 16, "128");
+ttable_cipher!(Aes192, 24, "192");
+ttable_cipher!(Aes256, 32, "256");
+
+#[cfg(test)]
+mod test {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+    use crate::cipher;
+    use crate::test_util::hex_array;
+
+    #[test]
+    fn test_tables_match_sbox() {
+        for x in 0..=255u8 {
+            assert_eq!(SBOX[x as usize], crate::sbox::substitute(x));
+            assert_eq!(INVERSE_SBOX[x as usize], crate::sbox::inverse_substitute(x));
+        }
+    }
+
+    #[test]
+    fn test_fips_197_c3() {
+        let cipher = Aes256::new(&Key::new(hex_array(
+            "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f",
+        )));
+        let plaintext = hex_array("00112233445566778899aabbccddeeff");
+        let ciphertext = hex_array("8ea2b7ca516745bfeafc49904b496089");
+
+        assert_eq!(cipher.encrypt_block(&plaintext), ciphertext);
+        assert_eq!(cipher.decrypt_block(&ciphertext), plaintext);
+    }
+
+    macro_rules! cross_check {
+        ($test:ident, $cipher:ident, $key_len:literal) => {
+            #[test]
+            fn $test() {
+                let mut rng = StdRng::seed_from_u64($key_len);
+
+                for _ in 0..100 {
+                    let key = Key::new(rng.gen::<[u8; $key_len]>());
+                    let fast = $cipher::new(&key);
+                    // `new` may pick AES-NI, so compare against the software rounds explicitly.
+                    let reference = cipher::$cipher::software(&key);
+                    let block = rng.gen::<[u8; 16]>();
+
+                    let encrypted = fast.encrypt_block(&block);
+                    assert_eq!(encrypted, reference.encrypt_block(&block));
+                    assert_eq!(fast.decrypt_block(&encrypted), block);
+                }
+            }
+        };
+    }
+
+    cross_check!(test_matches_reference_aes128, Aes128, 16);
+    cross_check!(test_matches_reference_aes192, Aes192, 24);
+    cross_check!(test_matches_reference_aes256, Aes256, 32);
+}
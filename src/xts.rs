@@ -0,0 +1,488 @@
+// This file holds the XTS mode from IEEE P1619 (NIST SP 800-38E), which encrypts fixed-size
+// storage sectors so that each can be read and written independently of the others.
+
+use crate::cipher::{Aes256, BlockCipher};
+use crate::error::{Error, Result};
+use crate::gcm::constant_time_eq;
+use crate::key::Key256;
+
+const BLOCK_SIZE: usize = 16;
+
+/// The longest sector P1619 allows, 2^20 blocks.
+const MAX_SECTOR_LEN: usize = (1 << 20) * BLOCK_SIZE;
+
+/// The number of blocks handed to the cipher at a time.
+const BATCH_BLOCKS: usize = 8;
+
+/// XTS over a pair of block ciphers: one for the data (Key1) and one for the tweak (Key2).
+///
+/// Every sector (a "data unit" in P1619) is encrypted under a tweak derived from its sector
+/// number, so identical plaintext in different sectors gives different ciphertext. The
+/// ciphertext is exactly as long as the plaintext; a sector that is not a whole number of blocks
+/// is finished with ciphertext stealing. Sectors must be at least one block and at most 2^20
+/// blocks long.
+pub struct Xts<C> {
+    cipher: C,
+    tweak_cipher: C,
+}
+
+/// XTS-AES-256, keyed with two independent 256-bit keys.
+pub type XtsAes256 = Xts<Aes256>;
+
+impl Xts<Aes256> {
+    /// Creates XTS-AES-256 from the data key (Key1) and the tweak key (Key2). The two keys must
+    /// be generated independently; fails with [`Error::WeakKey`] if they are equal, as
+    /// SP 800-38E requires.
+    pub fn from_keys(data_key: &Key256, tweak_key: &Key256) -> Result<Self> {
+        if constant_time_eq(data_key.as_bytes(), tweak_key.as_bytes()) {
+            return Err(Error::WeakKey);
+        }
+
+        Ok(Xts::new(Aes256::new(data_key), Aes256::new(tweak_key)))
+    }
+}
+
+impl<C: BlockCipher> Xts<C> {
+    /// Creates XTS from two ciphers. Unlike [`Xts::from_keys`], this cannot check that the
+    /// ciphers were built from different keys.
+    pub fn new(cipher: C, tweak_cipher: C) -> Self {
+        Xts {
+            cipher,
+            tweak_cipher,
+        }
+    }
+
+    /// Encrypts a single sector in place. Fails with [`Error::InputTooShort`] if the sector is
+    /// shorter than a block and [`Error::InputTooLong`] if it is longer than 2^20 blocks.
+    pub fn encrypt_sector(&self, sector: u128, data: &mut [u8]) -> Result<()> {
+        if data.len() < BLOCK_SIZE {
+            return Err(Error::InputTooShort(data.len()));
+        }
+        if data.len() > MAX_SECTOR_LEN {
+            return Err(Error::InputTooLong(data.len()));
+        }
+
+        let partial = data.len() % BLOCK_SIZE;
+        let (blocks, tail) = split_sector(data);
+        let tweak = self.apply_blocks(sector, blocks, |cipher, blocks| {
+            cipher.encrypt_blocks(blocks)
+        });
+        if partial == 0 {
+            return Ok(());
+        }
+
+        // Encrypt the last full block normally, swap its head with the partial block, then
+        // encrypt the result again under the next tweak.
+        let (last, stolen) = tail.split_at_mut(BLOCK_SIZE);
+        let last: &mut [u8; BLOCK_SIZE] = last.try_into().unwrap();
+        *last = self.encrypt_block(last, tweak);
+        last[..partial].swap_with_slice(stolen);
+        *last = self.encrypt_block(last, multiply_by_alpha(tweak));
+
+        Ok(())
+    }
+
+    /// Decrypts a single sector in place. Fails with [`Error::TruncatedInput`] if the sector is
+    /// shorter than a block and [`Error::InputTooLong`] if it is longer than 2^20 blocks.
+    pub fn decrypt_sector(&self, sector: u128, data: &mut [u8]) -> Result<()> {
+        if data.len() < BLOCK_SIZE {
+            return Err(Error::TruncatedInput);
+        }
+        if data.len() > MAX_SECTOR_LEN {
+            return Err(Error::InputTooLong(data.len()));
+        }
+
+        let partial = data.len() % BLOCK_SIZE;
+        let (blocks, tail) = split_sector(data);
+        let tweak = self.apply_blocks(sector, blocks, |cipher, blocks| {
+            cipher.decrypt_blocks(blocks)
+        });
+        if partial == 0 {
+            return Ok(());
+        }
+
+        // The stolen blocks were encrypted in the opposite tweak order, so undo the last one
+        // first.
+        let (last, stolen) = tail.split_at_mut(BLOCK_SIZE);
+        let last: &mut [u8; BLOCK_SIZE] = last.try_into().unwrap();
+        *last = self.decrypt_block(last, multiply_by_alpha(tweak));
+        last[..partial].swap_with_slice(stolen);
+        *last = self.decrypt_block(last, tweak);
+
+        Ok(())
+    }
+
+    /// Encrypts consecutive sectors of `sector_size` bytes in place, numbering them from
+    /// `first_sector`. A final sector shorter than `sector_size` is encrypted on its own.
+    ///
+    /// Fails with [`Error::InvalidSectorSize`] unless `sector_size` is from one block to 2^20
+    /// blocks, with [`Error::InputTooShort`] if the final sector is shorter than a block, and with
+    /// [`Error::InputTooLong`] if the sector numbers would pass `u128::MAX`. All of these are
+    /// checked before any data is changed.
+    pub fn encrypt_sectors(
+        &self,
+        first_sector: u128,
+        sector_size: usize,
+        data: &mut [u8],
+    ) -> Result<()> {
+        check_sectors(first_sector, sector_size, data.len())?;
+        if let Some(len) = short_final_sector(sector_size as u64, data.len() as u64) {
+            return Err(Error::InputTooShort(len));
+        }
+        for (i, chunk) in data.chunks_mut(sector_size).enumerate() {
+            self.encrypt_sector(first_sector + i as u128, chunk)?;
+        }
+        Ok(())
+    }
+
+    /// Decrypts consecutive sectors produced by [`Xts::encrypt_sectors`] in place. Fails in the
+    /// same cases, except that a short final sector is reported as [`Error::TruncatedInput`].
+    pub fn decrypt_sectors(
+        &self,
+        first_sector: u128,
+        sector_size: usize,
+        data: &mut [u8],
+    ) -> Result<()> {
+        check_sectors(first_sector, sector_size, data.len())?;
+        if short_final_sector(sector_size as u64, data.len() as u64).is_some() {
+            return Err(Error::TruncatedInput);
+        }
+        for (i, chunk) in data.chunks_mut(sector_size).enumerate() {
+            self.decrypt_sector(first_sector + i as u128, chunk)?;
+        }
+        Ok(())
+    }
+
+    /// Runs `apply` over the blocks in batches, with each block's tweak XORed in before and
+    /// after. Returns the tweak for the block that follows them.
+    fn apply_blocks(
+        &self,
+        sector: u128,
+        blocks: &mut [[u8; BLOCK_SIZE]],
+        apply: impl Fn(&C, &mut [[u8; BLOCK_SIZE]]),
+    ) -> u128 {
+        let mut tweak = u128::from_le_bytes(self.tweak_cipher.encrypt_block(&sector.to_le_bytes()));
+        let mut tweaks = [0; BATCH_BLOCKS];
+
+        for batch in blocks.chunks_mut(BATCH_BLOCKS) {
+            for (block, t) in batch.iter_mut().zip(tweaks.iter_mut()) {
+                *t = tweak;
+                xor_tweak(block, tweak);
+                tweak = multiply_by_alpha(tweak);
+            }
+            apply(&self.cipher, batch);
+            for (block, t) in batch.iter_mut().zip(&tweaks) {
+                xor_tweak(block, *t);
+            }
+        }
+
+        tweak
+    }
+
+    fn encrypt_block(&self, block: &[u8; BLOCK_SIZE], tweak: u128) -> [u8; BLOCK_SIZE] {
+        let mut block = *block;
+        xor_tweak(&mut block, tweak);
+        block = self.cipher.encrypt_block(&block);
+        xor_tweak(&mut block, tweak);
+        block
+    }
+
+    fn decrypt_block(&self, block: &[u8; BLOCK_SIZE], tweak: u128) -> [u8; BLOCK_SIZE] {
+        let mut block = *block;
+        xor_tweak(&mut block, tweak);
+        block = self.cipher.decrypt_block(&block);
+        xor_tweak(&mut block, tweak);
+        block
+    }
+}
+
+/// Checks that `sector_size` is a valid sector length and that numbering the sectors in `len`
+/// bytes from `first_sector` does not overflow.
+pub(crate) fn check_sectors(first_sector: u128, sector_size: usize, len: usize) -> Result<()> {
+    if !(BLOCK_SIZE..=MAX_SECTOR_LEN).contains(&sector_size) {
+        return Err(Error::InvalidSectorSize(sector_size));
+    }
+
+    let sectors = len.div_ceil(sector_size) as u128;
+    if sectors > 0 && first_sector.checked_add(sectors - 1).is_none() {
+        return Err(Error::InputTooLong(len));
+    }
+
+    Ok(())
+}
+
+/// Returns the length of the final sector in `len` bytes if it is too short to process. Checked
+/// up front so that a bad final sector does not leave the earlier ones already transformed.
+pub(crate) fn short_final_sector(sector_size: u64, len: u64) -> Option<usize> {
+    let partial = len % sector_size;
+    (partial > 0 && partial < BLOCK_SIZE as u64).then_some(partial as usize)
+}
+
+/// Splits a sector into the blocks processed normally and the final full block plus partial
+/// block that take part in ciphertext stealing, if any.
+fn split_sector(data: &mut [u8]) -> (&mut [[u8; BLOCK_SIZE]], &mut [u8]) {
+    let normal = match data.len() % BLOCK_SIZE {
+        0 => data.len(),
+        partial => data.len() - partial - BLOCK_SIZE,
+    };
+    let (blocks, tail) = data.split_at_mut(normal);
+    (blocks.as_chunks_mut().0, tail)
+}
+
+/// Multiplies the tweak by the primitive element x of GF(2^128), using the little-endian
+/// convention of P1619, without branching on the tweak.
+fn multiply_by_alpha(tweak: u128) -> u128 {
+    (tweak << 1) ^ ((tweak >> 127) * 0x87)
+}
+
+fn xor_tweak(block: &mut [u8; BLOCK_SIZE], tweak: u128) {
+    *block = (u128::from_le_bytes(*block) ^ tweak).to_le_bytes();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cipher::Aes128;
+    use crate::key::Key;
+    use crate::test_util::{hex, hex_array};
+
+    /// The 512-byte plaintext shared by the longer P1619 vectors: 0x00 to 0xff, twice.
+    fn counting_sector() -> Vec<u8> {
+        (0..512).map(|i| i as u8).collect()
+    }
+
+    fn xts_aes256(key1: &str, key2: &str) -> XtsAes256 {
+        Xts::from_keys(&Key::new(hex_array(key1)), &Key::new(hex_array(key2))).unwrap()
+    }
+
+    fn xts_aes128(key1: &str, key2: &str) -> Xts<Aes128> {
+        Xts::new(
+            Aes128::new(&Key::new(hex_array(key1))),
+            Aes128::new(&Key::new(hex_array(key2))),
+        )
+    }
+
+    fn check<C: BlockCipher>(xts: &Xts<C>, sector: u128, plaintext: &[u8], ciphertext: &str) {
+        let mut data = plaintext.to_vec();
+        xts.encrypt_sector(sector, &mut data).unwrap();
+        assert_eq!(data, hex(ciphertext));
+
+        xts.decrypt_sector(sector, &mut data).unwrap();
+        assert_eq!(data, plaintext);
+    }
+
+    #[test]
+    fn test_ieee_1619_vector_2() {
+        let xts = xts_aes128(
+            "11111111111111111111111111111111",
+            "22222222222222222222222222222222",
+        );
+        check(
+            &xts,
+            0x3333333333,
+            &[0x44; 32],
+            "c454185e6a16936e39334038acef838bfb186fff7480adc4289382ecd6d394f0",
+        );
+    }
+
+    #[test]
+    fn test_ieee_1619_vector_10() {
+        let xts = xts_aes256(
+            "2718281828459045235360287471352662497757247093699959574966967627",
+            "3141592653589793238462643383279502884197169399375105820974944592",
+        );
+        check(
+            &xts,
+            0xff,
+            &counting_sector(),
+            "
+            1c3b3a102f770386e4836c99e370cf9bea00803f5e482357a4ae12d414a3e63b
+            5d31e276f8fe4a8d66b317f9ac683f44680a86ac35adfc3345befecb4bb188fd
+            5776926c49a3095eb108fd1098baec70aaa66999a72a82f27d848b21d4a741b0
+            c5cd4d5fff9dac89aeba122961d03a757123e9870f8acf1000020887891429ca
+            2a3e7a7d7df7b10355165c8b9a6d0a7de8b062c4500dc4cd120c0f7418dae3d0
+            b5781c34803fa75421c790dfe1de1834f280d7667b327f6c8cd7557e12ac3a0f
+            93ec05c52e0493ef31a12d3d9260f79a289d6a379bc70c50841473d1a8cc81ec
+            583e9645e07b8d9670655ba5bbcfecc6dc3966380ad8fecb17b6ba02469a020a
+            84e18e8f84252070c13e9f1f289be54fbc481457778f616015e1327a02b140f1
+            505eb309326d68378f8374595c849d84f4c333ec4423885143cb47bd71c5edae
+            9be69a2ffeceb1bec9de244fbe15992b11b77c040f12bd8f6a975a44a0f90c29
+            a9abc3d4d893927284c58754cce294529f8614dcd2aba991925fedc4ae74ffac
+            6e333b93eb4aff0479da9a410e4450e0dd7ae4c6e2910900575da401fc07059f
+            645e8b7e9bfdef33943054ff84011493c27b3429eaedb4ed5376441a77ed4385
+            1ad77f16f541dfd269d50d6a5f14fb0aab1cbb4c1550be97f7ab4066193c4caa
+            773dad38014bd2092fa755c824bb5e54c4f36ffda9fcea70b9c6e693e148c151",
+        );
+    }
+
+    #[test]
+    fn test_ieee_1619_vector_11() {
+        let xts = xts_aes256(
+            "2718281828459045235360287471352662497757247093699959574966967627",
+            "3141592653589793238462643383279502884197169399375105820974944592",
+        );
+        check(
+            &xts,
+            0xffff,
+            &counting_sector(),
+            "
+            77a31251618a15e6b92d1d66dffe7b50b50bad552305ba0217a610688eff7e11
+            e1d0225438e093242d6db274fde801d4cae06f2092c728b2478559df58e837c2
+            469ee4a4fa794e4bbc7f39bc026e3cb72c33b0888f25b4acf56a2a9804f1ce6d
+            3d6e1dc6ca181d4b546179d55544aa7760c40d06741539c7e3cd9d2f6650b201
+            3fd0eeb8c2b8e3d8d240ccae2d4c98320a7442e1c8d75a42d6e6cfa4c2eca179
+            8d158c7aecdf82490f24bb9b38e108bcda12c3faf9a21141c3613b58367f922a
+            aa26cd22f23d708dae699ad7cb40a8ad0b6e2784973dcb605684c08b8d6998c6
+            9aac049921871ebb65301a4619ca80ecb485a31d744223ce8ddc2394828d6a80
+            470c092f5ba413c3378fa6054255c6f9df4495862bbb3287681f931b687c888a
+            bf844dfc8fc28331e579928cd12bd2390ae123cf03818d14dedde5c0c24c8ab0
+            18bfca75ca096f2d531f3d1619e785f1ada437cab92e980558b3dce1474afb75
+            bfedbf8ff54cb2618e0244c9ac0d3c66fb51598cd2db11f9be39791abe447c63
+            094f7c453b7ff87cb5bb36b7c79efb0872d17058b83b15ab0866ad8a58656c5a
+            7e20dbdf308b2461d97c0ec0024a2715055249cf3b478ddd4740de654f75ca68
+            6e0d7345c69ed50cdc2a8b332b1f8824108ac937eb050585608ee734097fc090
+            54fbff89eeaeea791f4a7ab1f9868294a4f9e27b42af8100cb9d59cef9645803",
+        );
+    }
+
+    #[test]
+    fn test_ieee_1619_partial_sectors() {
+        // Vectors 15 to 18 cover ciphertext stealing with 1 to 4 bytes past the last block.
+        let xts = xts_aes128(
+            "fffefdfcfbfaf9f8f7f6f5f4f3f2f1f0",
+            "bfbebdbcbbbab9b8b7b6b5b4b3b2b1b0",
+        );
+        let vectors = [
+            "6c1625db4671522d3d7599601de7ca09ed",
+            "d069444b7a7e0cab09e24447d24deb1fedbf",
+            "e5df1351c0544ba1350b3363cd8ef4beedbf9d",
+            "9d84c813f719aa2c7be3f66171c7c5c2edbf9dac",
+        ];
+
+        for ciphertext in vectors {
+            let plaintext: Vec<u8> = (0..ciphertext.len() as u8 / 2).collect();
+            check(&xts, 0x123456789a, &plaintext, ciphertext);
+        }
+    }
+
+    #[test]
+    fn test_sectors_are_independent() {
+        let xts = xts_aes256(
+            "2718281828459045235360287471352662497757247093699959574966967627",
+            "3141592653589793238462643383279502884197169399375105820974944592",
+        );
+        let plaintext: Vec<u8> = (0..3 * 512 + 37).map(|i| i as u8).collect();
+
+        let mut data = plaintext.clone();
+        xts.encrypt_sectors(7, 512, &mut data).unwrap();
+        for (i, (chunk, original)) in data.chunks(512).zip(plaintext.chunks(512)).enumerate() {
+            let mut sector = original.to_vec();
+            xts.encrypt_sector(7 + i as u128, &mut sector).unwrap();
+            assert_eq!(chunk, sector);
+        }
+
+        xts.decrypt_sectors(7, 512, &mut data).unwrap();
+        assert_eq!(data, plaintext);
+    }
+
+    #[test]
+    fn test_rejects_short_sectors() {
+        let xts = xts_aes128(
+            "fffefdfcfbfaf9f8f7f6f5f4f3f2f1f0",
+            "bfbebdbcbbbab9b8b7b6b5b4b3b2b1b0",
+        );
+        assert!(matches!(
+            xts.encrypt_sector(0, &mut [0; 15]),
+            Err(Error::InputTooShort(15))
+        ));
+        assert!(matches!(
+            xts.decrypt_sector(0, &mut [0; 15]),
+            Err(Error::TruncatedInput)
+        ));
+    }
+
+    #[test]
+    fn test_rejects_equal_keys() {
+        let key = Key::new([0x5a; 32]);
+        assert!(matches!(
+            Xts::from_keys(&key, &key.clone()),
+            Err(Error::WeakKey)
+        ));
+    }
+
+    #[test]
+    fn test_rejects_long_sectors() {
+        let xts = xts_aes128(
+            "fffefdfcfbfaf9f8f7f6f5f4f3f2f1f0",
+            "bfbebdbcbbbab9b8b7b6b5b4b3b2b1b0",
+        );
+        let mut data = vec![0; MAX_SECTOR_LEN + 1];
+        assert!(matches!(
+            xts.encrypt_sector(0, &mut data),
+            Err(Error::InputTooLong(n)) if n == MAX_SECTOR_LEN + 1
+        ));
+        assert!(matches!(
+            xts.decrypt_sector(0, &mut data),
+            Err(Error::InputTooLong(n)) if n == MAX_SECTOR_LEN + 1
+        ));
+        assert!(xts.encrypt_sector(0, &mut data[..MAX_SECTOR_LEN]).is_ok());
+    }
+
+    #[test]
+    fn test_sectors_reject_bad_parameters() {
+        let xts = xts_aes128(
+            "fffefdfcfbfaf9f8f7f6f5f4f3f2f1f0",
+            "bfbebdbcbbbab9b8b7b6b5b4b3b2b1b0",
+        );
+        let mut data = [0; 64];
+
+        for sector_size in [0, 15, MAX_SECTOR_LEN + 1] {
+            assert!(matches!(
+                xts.encrypt_sectors(0, sector_size, &mut data),
+                Err(Error::InvalidSectorSize(n)) if n == sector_size
+            ));
+            assert!(matches!(
+                xts.decrypt_sectors(0, sector_size, &mut data),
+                Err(Error::InvalidSectorSize(n)) if n == sector_size
+            ));
+        }
+
+        // Four sectors numbered from u128::MAX - 3 end exactly at u128::MAX.
+        assert!(xts.encrypt_sectors(u128::MAX - 3, 16, &mut data).is_ok());
+        assert!(matches!(
+            xts.encrypt_sectors(u128::MAX - 2, 16, &mut data),
+            Err(Error::InputTooLong(64))
+        ));
+        assert!(matches!(
+            xts.decrypt_sectors(u128::MAX, 32, &mut data),
+            Err(Error::InputTooLong(64))
+        ));
+    }
+
+    #[test]
+    fn test_sectors_reject_short_final_sector_untouched() {
+        let xts = xts_aes128(
+            "fffefdfcfbfaf9f8f7f6f5f4f3f2f1f0",
+            "bfbebdbcbbbab9b8b7b6b5b4b3b2b1b0",
+        );
+        let original: Vec<u8> = (0..32 * 3 + 15).map(|i| i as u8).collect();
+        let mut data = original.clone();
+
+        assert!(matches!(
+            xts.encrypt_sectors(0, 32, &mut data),
+            Err(Error::InputTooShort(15))
+        ));
+        assert_eq!(data, original);
+        assert!(matches!(
+            xts.decrypt_sectors(0, 32, &mut data),
+            Err(Error::TruncatedInput)
+        ));
+        assert_eq!(data, original);
+
+        // A final sector of at least a block is fine.
+        data.push(0);
+        xts.encrypt_sectors(0, 32, &mut data).unwrap();
+        xts.decrypt_sectors(0, 32, &mut data).unwrap();
+        assert_eq!(data[..original.len()], original);
+    }
+}
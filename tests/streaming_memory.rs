@@ -0,0 +1,184 @@
+// Decrypts very large synthetic streams while tracking every heap allocation, to check that the
+// streaming decryptors only ever hold back the final blocks instead of the whole stream.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+use raes::cbc::{self, CbcEncryptor};
+use raes::shared::decrypt_stream;
+use raes::{Aes256, Key};
+
+struct TrackingAllocator;
+
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+/// The counters are shared by every test in this binary and cargo runs tests in parallel, so each
+/// test holds this for its whole run to keep the others' allocations out of its measurement.
+static MEASURING: Mutex<()> = Mutex::new(());
+
+unsafe impl GlobalAlloc for TrackingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            let current = CURRENT.fetch_add(layout.size(), Ordering::SeqCst) + layout.size();
+            PEAK.fetch_max(current, Ordering::SeqCst);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        CURRENT.fetch_sub(layout.size(), Ordering::SeqCst);
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: TrackingAllocator = TrackingAllocator;
+
+/// No decryption may grow the heap by more than this, whatever the stream length.
+const MEMORY_BUDGET: usize = 64 * 1024;
+
+/// The stream length used by the default test run, kept several times larger than the budget so
+/// buffering the whole stream would still fail. The ignored tests use several gigabytes.
+const DEFAULT_LEN: u64 = 256 * 1024;
+const LARGE_LEN: u64 = 3 * 1024 * 1024 * 1024;
+
+const KEY: [u8; 32] = [0x5c; 32];
+
+/// Produces `remaining` bytes of ciphertext on demand, one block at a time, without ever
+/// materialising the whole stream.
+struct SyntheticReader<F> {
+    next_block: F,
+    block: [u8; 16],
+    offset: usize,
+    remaining: u64,
+}
+
+impl<F: FnMut() -> [u8; 16]> SyntheticReader<F> {
+    fn new(len: u64, next_block: F) -> Self {
+        SyntheticReader {
+            next_block,
+            block: [0; 16],
+            offset: 16,
+            remaining: len,
+        }
+    }
+}
+
+impl<F: FnMut() -> [u8; 16]> Read for SyntheticReader<F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut written = 0;
+        while written < buf.len() && self.remaining > 0 {
+            if self.offset == 16 {
+                self.block = (self.next_block)();
+                self.offset = 0;
+            }
+            buf[written] = self.block[self.offset];
+            self.offset += 1;
+            self.remaining -= 1;
+            written += 1;
+        }
+        Ok(written)
+    }
+}
+
+/// Counts the plaintext bytes and checks that they are all zero, without storing them.
+#[derive(Default)]
+struct ZeroCheckingSink {
+    len: u64,
+}
+
+impl Write for ZeroCheckingSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        assert!(buf.iter().all(|&b| b == 0), "plaintext was corrupted");
+        self.len += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Takes the measurement lock. A test that panicked while holding it leaves nothing to clean up,
+/// so a poisoned lock is used as it is.
+fn exclusive() -> MutexGuard<'static, ()> {
+    MEASURING
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Runs `decrypt` and returns how far the heap grew above its starting point. The caller must
+/// hold the guard from [`exclusive`].
+fn peak_growth(decrypt: impl FnOnce()) -> usize {
+    let baseline = CURRENT.load(Ordering::SeqCst);
+    PEAK.store(baseline, Ordering::SeqCst);
+    decrypt();
+    PEAK.load(Ordering::SeqCst).saturating_sub(baseline)
+}
+
+fn check_ecb_stream(len: u64) {
+    let _guard = exclusive();
+    // ECB of an all-zero plaintext repeats the same ciphertext block.
+    let zero_block = Aes256::new(&Key::new(KEY)).encrypt_block(&[0; 16]);
+    let reader = SyntheticReader::new(len, || zero_block);
+
+    let mut sink = ZeroCheckingSink::default();
+    let growth = peak_growth(|| decrypt_stream(reader, &mut sink, &Key::new(KEY)).unwrap());
+
+    assert_eq!(sink.len, len);
+    assert!(growth < MEMORY_BUDGET, "heap grew by {} bytes", growth);
+}
+
+fn check_cbc_stream(len: u64) {
+    let _guard = exclusive();
+    let cipher = Aes256::new(&Key::new(KEY));
+    let iv = [0x11; 16];
+
+    // The version byte and IV come first, then the encryption of `len` zero bytes and a final
+    // padding block.
+    let mut encryptor = CbcEncryptor::new(&cipher, &iv);
+    let mut blocks_left = len / 16 + 1;
+    let mut header_sent = false;
+    let blocks = SyntheticReader::new(16 + len + 16, || {
+        if !header_sent {
+            header_sent = true;
+            return iv;
+        }
+        blocks_left -= 1;
+        let block = if blocks_left == 0 { [16; 16] } else { [0; 16] };
+        encryptor.encrypt_block(&block)
+    });
+    let reader = [cbc::FORMAT_VERSION].chain(blocks);
+
+    let mut sink = ZeroCheckingSink::default();
+    let growth = peak_growth(|| cbc::decrypt_stream(reader, &mut sink, &cipher).unwrap());
+
+    assert_eq!(sink.len, len);
+    assert!(growth < MEMORY_BUDGET, "heap grew by {} bytes", growth);
+}
+
+#[test]
+fn test_ecb_decrypt_stream_bounded_memory() {
+    check_ecb_stream(DEFAULT_LEN);
+}
+
+#[test]
+fn test_cbc_decrypt_stream_bounded_memory() {
+    check_cbc_stream(DEFAULT_LEN);
+}
+
+#[test]
+#[ignore = "decrypts several gigabytes; run with --release -- --ignored"]
+fn test_ecb_decrypt_stream_multi_gigabyte() {
+    check_ecb_stream(LARGE_LEN);
+}
+
+#[test]
+#[ignore = "decrypts several gigabytes; run with --release -- --ignored"]
+fn test_cbc_decrypt_stream_multi_gigabyte() {
+    check_cbc_stream(LARGE_LEN);
+}